@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use nbs::{io::DecodeLimits, Nbs};
+
+fuzz_target!(|data: &[u8]| {
+    // Decoding must never panic, neither with the default limits nor with strict ones.
+    let limits = DecodeLimits {
+        max_string_length: 1024,
+        max_layers: 256,
+        max_ticks: 4096,
+        max_custom_instruments: 16,
+    };
+    let _ = Nbs::decode_with_limits(&mut &data[..], &limits);
+    if let Ok(nbs) = Nbs::decode(&mut &data[..]) {
+        let mut buffer = Vec::new();
+        let _ = nbs.encode(&mut buffer);
+    }
+});
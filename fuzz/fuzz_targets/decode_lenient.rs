@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use nbs::{io::DecodeLimits, Nbs};
+
+fuzz_target!(|data: &[u8]| {
+    // Repairing damaged data must never panic, and neither must fixing and encoding the repaired song.
+    if let Ok((mut nbs, _)) = Nbs::decode_lenient(&mut &data[..], &DecodeLimits::default()) {
+        nbs.fix();
+        let mut buffer = Vec::new();
+        let _ = nbs.encode(&mut buffer);
+    }
+});
@@ -0,0 +1,132 @@
+//! Minimal writers for the container formats the exporters need.
+//! Data is stored without compression, which every reader supports and keeps this crate free of dependencies.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::{
+    convert::{TryFrom, TryInto},
+    io::{self, Write},
+};
+
+/// The DOS date of 1980-01-01, the earliest a zip entry can have.
+const ZIP_DATE: u16 = 0x21;
+
+/// Returns the CRC-32 (IEEE) checksum of the data, as used by gzip and zip.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Writes the data as a gzip stream made of stored deflate blocks.
+pub(crate) fn write_gzip<W>(data: &[u8], writer: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    // Magic, deflate, no flags, no modification time, no extra flags, unknown OS.
+    writer.write_all(&[0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF])?;
+    let mut chunks = data.chunks(u16::MAX as usize).peekable();
+    if chunks.peek().is_none() {
+        // An empty stream still needs a final block.
+        writer.write_all(&[1, 0, 0, 0xFF, 0xFF])?;
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        writer.write_u8(last as u8)?;
+        writer.write_u16::<LittleEndian>(chunk.len() as u16)?;
+        writer.write_u16::<LittleEndian>(!(chunk.len() as u16))?;
+        writer.write_all(chunk)?;
+    }
+    writer.write_u32::<LittleEndian>(crc32(data))?;
+    writer.write_u32::<LittleEndian>(data.len() as u32)?;
+    Ok(())
+}
+
+/// Writes a zip archive of stored entries, with the central directory written by `finish`.
+pub(crate) struct ZipWriter<W> {
+    writer: W,
+    offset: u32,
+    /// Name, checksum, size and offset of every written entry.
+    entries: Vec<(String, u32, u32, u32)>,
+}
+
+impl<W> ZipWriter<W>
+where
+    W: Write,
+{
+    pub(crate) fn new(writer: W) -> Self {
+        ZipWriter {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "zip archive is too large");
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        if self.entries.len() == u16::MAX as usize {
+            return Err(too_large());
+        }
+        let crc = crc32(data);
+        let writer = &mut self.writer;
+        writer.write_u32::<LittleEndian>(0x0403_4B50)?;
+        writer.write_u16::<LittleEndian>(20)?; // Version needed to extract.
+        writer.write_u16::<LittleEndian>(1 << 11)?; // The name is UTF-8.
+        writer.write_u16::<LittleEndian>(0)?; // Stored.
+        writer.write_u16::<LittleEndian>(0)?;
+        writer.write_u16::<LittleEndian>(ZIP_DATE)?;
+        writer.write_u32::<LittleEndian>(crc)?;
+        writer.write_u32::<LittleEndian>(size)?;
+        writer.write_u32::<LittleEndian>(size)?;
+        writer.write_u16::<LittleEndian>(name.len() as u16)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_all(data)?;
+        self.entries
+            .push((name.to_string(), crc, size, self.offset));
+        self.offset = (30 + name.len() as u64 + size as u64 + self.offset as u64)
+            .try_into()
+            .map_err(|_| too_large())?;
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        let writer = &mut self.writer;
+        let mut directory_size = 0;
+        for (name, crc, size, offset) in &self.entries {
+            writer.write_u32::<LittleEndian>(0x0201_4B50)?;
+            writer.write_u16::<LittleEndian>(20)?; // Version made by.
+            writer.write_u16::<LittleEndian>(20)?;
+            writer.write_u16::<LittleEndian>(1 << 11)?;
+            writer.write_u16::<LittleEndian>(0)?;
+            writer.write_u16::<LittleEndian>(0)?;
+            writer.write_u16::<LittleEndian>(ZIP_DATE)?;
+            writer.write_u32::<LittleEndian>(*crc)?;
+            writer.write_u32::<LittleEndian>(*size)?;
+            writer.write_u32::<LittleEndian>(*size)?;
+            writer.write_u16::<LittleEndian>(name.len() as u16)?;
+            // Extra field, comment, disk number, internal and external attributes.
+            writer.write_all(&[0; 12])?;
+            writer.write_u32::<LittleEndian>(*offset)?;
+            writer.write_all(name.as_bytes())?;
+            directory_size += 46 + name.len() as u32;
+        }
+        writer.write_u32::<LittleEndian>(0x0605_4B50)?;
+        writer.write_u32::<LittleEndian>(0)?; // This disk and the disk with the directory.
+        writer.write_u16::<LittleEndian>(self.entries.len() as u16)?;
+        writer.write_u16::<LittleEndian>(self.entries.len() as u16)?;
+        writer.write_u32::<LittleEndian>(directory_size)?;
+        writer.write_u32::<LittleEndian>(self.offset)?;
+        writer.write_u16::<LittleEndian>(0)?;
+        Ok(self.writer)
+    }
+}
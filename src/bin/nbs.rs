@@ -0,0 +1,292 @@
+//! Command-line tool for inspecting and converting NBS files.
+
+use nbs::{
+    io::DecodeLimits,
+    midi::{self, ImportOptions, InstrumentMap},
+    noteblocks::instrument::Instrument,
+    validation::Severity,
+    Nbs, NbsFormat,
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    process,
+};
+
+const USAGE: &str = "Usage: nbs <command> [arguments]
+
+Commands:
+    info <file>                       Print the header, duration, layers and instruments of a song
+    convert <input> <output> [--version <0-5>]
+                                      Convert between NBS versions, JSON and MIDI, chosen by the file extensions.
+                                      Version 0 is the classic NoteBlockStudio format.
+    validate <file>                   Check a file for problems, or list what would have to be repaired if it can not be decoded
+    dump <file>                       List every note tick by tick
+
+Files ending in .json are read and written as JSON, files ending in .mid or .midi as Standard MIDI Files.";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("info") => single_file(&args).and_then(info),
+        Some("convert") => convert(&args[1..]),
+        Some("validate") => single_file(&args).and_then(validate),
+        Some("dump") => single_file(&args).and_then(dump),
+        Some("help") | Some("--help") | Some("-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(USAGE.into()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn single_file(args: &[String]) -> Result<&str> {
+    match args {
+        [_, file] => Ok(file),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Returns the lowercase extension of a path.
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn read(path: &str) -> Result<Nbs> {
+    match extension(path).as_str() {
+        "json" => Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?),
+        "mid" | "midi" => Ok(midi::import_file(path, &ImportOptions::default())?),
+        _ => Ok(Nbs::decode(&mut BufReader::new(File::open(path)?))?),
+    }
+}
+
+fn write(nbs: &Nbs, path: &str) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match extension(path).as_str() {
+        "json" => serde_json::to_writer_pretty(&mut writer, nbs)?,
+        "mid" | "midi" => midi::encode(nbs, &InstrumentMap::default(), &mut writer)?,
+        _ => nbs.encode(&mut writer)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn format_name(format: NbsFormat) -> String {
+    match format {
+        NbsFormat::NoteBlockStudio => String::from("NoteBlockStudio (classic)"),
+        NbsFormat::OpenNoteBlockStudio(version) => {
+            format!("OpenNoteBlockStudio version {}", version)
+        }
+    }
+}
+
+fn instrument_name(nbs: &Nbs, instrument: Instrument) -> String {
+    const VANILLA: [&str; 16] = [
+        "Piano",
+        "Double Bass",
+        "Bass Drum",
+        "Snare Drum",
+        "Click",
+        "Guitar",
+        "Flute",
+        "Bell",
+        "Chime",
+        "Xylophone",
+        "Iron Xylophone",
+        "Cow Bell",
+        "Didgeridoo",
+        "Bit",
+        "Banjo",
+        "Pling",
+    ];
+    match instrument {
+        Instrument::Vanilla(id) => VANILLA
+            .get(id as usize)
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("Unknown ({})", id)),
+        Instrument::Custom(id) => match nbs.custom_instruments.get(instrument) {
+            Some(info) => format!("{} (custom)", info.name),
+            None => format!("Missing custom instrument ({})", id),
+        },
+    }
+}
+
+fn info(path: &str) -> Result<()> {
+    let nbs = read(path)?;
+    let header = &nbs.header;
+    println!("Format:               {}", format_name(nbs.format()));
+    println!("Song name:            {}", header.song_name);
+    println!("Song author:          {}", header.song_author);
+    println!("Original song author: {}", header.original_song_author);
+    println!("Description:          {}", header.song_description);
+    println!(
+        "Tempo:                {} ticks per second",
+        header.song_tempo as f32 / 100.0
+    );
+    println!("Time signature:       {}/4", header.time_signature);
+    if let Some(is_loop) = header.is_loop {
+        println!(
+            "Loop:                 {} (start tick {}, max loop count {})",
+            is_loop,
+            header.loop_start_tick.unwrap_or(0),
+            header.max_loop_count.unwrap_or(0)
+        );
+    }
+    println!("Minutes spent:        {}", header.minutes_spent);
+    println!("Imported file name:   {}", header.imported_file_name);
+    let tempo_map = nbs.tempo_map();
+    if !tempo_map.is_constant() {
+        println!("Tempo changes:        {}", tempo_map.changes().count() - 1);
+    }
+    println!("Ticks:                {}", nbs.song_ticks());
+    println!(
+        "Duration:             {:.2}s",
+        nbs.song_length().as_secs_f64()
+    );
+
+    println!("Layers:               {}", nbs.noteblocks.layers.len());
+    for (index, layer) in nbs.noteblocks.layers.iter().enumerate() {
+        if !layer.notes.is_empty() || !layer.name.is_empty() {
+            println!(
+                "    {:>3} {:<20} {:>5} notes, volume {}",
+                index,
+                layer.name,
+                layer.notes.len(),
+                layer.volume
+            );
+        }
+    }
+
+    let mut instruments: BTreeMap<u8, (Instrument, usize)> = BTreeMap::new();
+    for (_, _, note) in nbs.noteblocks.events() {
+        instruments
+            .entry(note.instrument.into())
+            .or_insert((note.instrument, 0))
+            .1 += 1;
+    }
+    println!("Instruments:          {}", instruments.len());
+    for (instrument, count) in instruments.values() {
+        println!(
+            "    {:<30} {:>5} notes",
+            instrument_name(&nbs, *instrument),
+            count
+        );
+    }
+    for info in nbs.custom_instruments.iter() {
+        println!(
+            "Custom instrument:    {} ({}, key {})",
+            info.name, info.file_name, info.pitch
+        );
+    }
+    Ok(())
+}
+
+fn convert(args: &[String]) -> Result<()> {
+    let (input, output, version) = match args {
+        [input, output] => (input, output, None),
+        [input, output, flag, version] if flag == "--version" => {
+            (input, output, Some(version.parse::<i8>()?))
+        }
+        _ => return Err(USAGE.into()),
+    };
+    let mut nbs = read(input)?;
+    if let Some(version) = version {
+        let format = if version == 0 {
+            NbsFormat::NoteBlockStudio
+        } else {
+            NbsFormat::OpenNoteBlockStudio(version)
+        };
+        if !format.is_supported() {
+            return Err(nbs::error::NbsError::UnsupportedVersion(version).into());
+        }
+        let report = nbs.convert_to(format);
+        if !report.is_lossless() {
+            eprintln!("Some information was lost: {:?}", report);
+        }
+    }
+    write(&nbs, output)
+}
+
+fn validate(path: &str) -> Result<()> {
+    let data = std::fs::read(path)?;
+    match Nbs::decode(&mut &data[..]) {
+        Ok(nbs) => {
+            let issues = nbs.validate();
+            for issue in &issues {
+                let fixable = if issue.kind.is_fixable() {
+                    " (fixable)"
+                } else {
+                    ""
+                };
+                println!("{}{}", issue, fixable);
+            }
+            if issues.iter().any(|issue| issue.severity == Severity::Error) {
+                Err("validation failed".into())
+            } else {
+                println!("{} is valid", path);
+                Ok(())
+            }
+        }
+        Err(e) => {
+            println!("{} is invalid: {}", path, e);
+            match Nbs::decode_lenient(&mut &data[..], &DecodeLimits::default()) {
+                Ok((_, warnings)) => {
+                    println!("It can be decoded leniently with these repairs:");
+                    for warning in warnings {
+                        println!("    {}", warning);
+                    }
+                }
+                Err(e) => println!("It can not be repaired: {}", e),
+            }
+            Err("validation failed".into())
+        }
+    }
+}
+
+fn dump(path: &str) -> Result<()> {
+    let nbs = read(path)?;
+    let tempo_map = nbs.tempo_map();
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for (tick, notes) in nbs.noteblocks.columns() {
+        writeln!(
+            out,
+            "Tick {} ({:.3}s)",
+            tick,
+            tempo_map.tick_to_time(tick).as_secs_f64()
+        )?;
+        for (layer_index, note) in notes {
+            write!(
+                out,
+                "    layer {:>3}: {:<24} key {:>2}",
+                layer_index,
+                instrument_name(&nbs, note.instrument),
+                note.key
+            )?;
+            if let (Some(velocity), Some(panning), Some(pitch)) =
+                (note.velocity, note.panning, note.pitch)
+            {
+                write!(
+                    out,
+                    ", velocity {}, panning {}, pitch {}",
+                    velocity, panning, pitch
+                )?;
+            }
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,273 @@
+//! Copying, cutting and pasting parts of songs, also between different songs.
+//!
+//! A `Fragment` holds the notes of a `Selection` together with the custom instruments they use,
+//! so it can be pasted into any song. Custom instruments are matched by their name, sound file and pitch,
+//! instruments the target song does not have yet are added to it.
+//!
+//! ## Example: Appending the start of a song to another song
+//!
+//! ```rust
+//! use nbs::{
+//!     clipboard::{PasteMode, Selection},
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let source = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let mut target = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let fragment = source.copy(&Selection::new(0..8, 0..source.noteblocks.layers.len()));
+//! let end = target.song_ticks() + 1;
+//! let report = target.paste(&fragment, end, 0, PasteMode::Overwrite).unwrap();
+//! assert_eq!(report.replaced, 0);
+//! ```
+
+use crate::{
+    conversion::ConversionReport,
+    noteblocks::{
+        instrument::{CustomInstrumentInfo, Instrument},
+        layer::Layer,
+        note::Note,
+    },
+    Nbs, NbsError, Tick,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    ops::Range,
+};
+
+/// A part of a song, spanning a range of ticks and a range of layers.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Selection {
+    pub ticks: Range<Tick>,
+    /// The indices of the layers.
+    pub layers: Range<usize>,
+}
+
+impl Selection {
+    pub fn new(ticks: Range<Tick>, layers: Range<usize>) -> Self {
+        Selection { ticks, layers }
+    }
+
+    /// Returns the amount of ticks the selection spans.
+    pub fn length(&self) -> Tick {
+        self.ticks.end.saturating_sub(self.ticks.start).max(0)
+    }
+
+    /// Returns the selected ticks, as an empty range if the end is before the start.
+    /// Like the length, the range ends at most `Tick::MAX` ticks after the start, so every tick fits relative to it.
+    fn tick_range(&self) -> Range<Tick> {
+        self.ticks.start..self.ticks.start.saturating_add(self.length())
+    }
+}
+
+/// The notes of a selection, which can be pasted into any song.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fragment {
+    /// The notes of every selected layer, by their tick relative to the start of the selection.
+    pub layers: Vec<BTreeMap<Tick, Note>>,
+    /// The amount of ticks the fragment spans, including silence at its end.
+    pub length: Tick,
+    /// The custom instruments used by the notes, with the ids of the song they were copied from.
+    pub custom_instruments: Vec<CustomInstrumentInfo>,
+    /// The amount of vanilla instruments of the song the notes were copied from.
+    pub vannila_instrument_count: u8,
+}
+
+/// How `Nbs::paste` treats the notes of the target song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteMode {
+    /// Pasted notes replace the notes on the same tick and layer.
+    Overwrite,
+    /// All notes from the paste tick onwards are moved back by the length of the fragment, in every layer.
+    Insert,
+}
+
+/// Describes what `Nbs::paste` changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PasteReport {
+    /// Amount of notes of the target song that were replaced.
+    pub replaced: usize,
+    /// Amount of layers that were added to fit the fragment.
+    pub added_layers: usize,
+    /// Amount of custom instruments that were added to the target song.
+    pub added_instruments: usize,
+    /// The information of the pasted notes that does not exist in the format of the target song.
+    pub conversion: ConversionReport,
+}
+
+/// Copies the notes of a selection.
+pub(crate) fn copy(nbs: &Nbs, selection: &Selection) -> Fragment {
+    let layers = selected_layers(nbs, selection);
+    let layers: Vec<BTreeMap<Tick, Note>> = nbs.noteblocks.layers[layers]
+        .iter()
+        .map(|layer| {
+            layer
+                .notes
+                .range(selection.tick_range())
+                .map(|(&tick, note)| (tick.saturating_sub(selection.ticks.start), note.clone()))
+                .collect()
+        })
+        .collect();
+    fragment(nbs, selection, layers)
+}
+
+/// Removes the notes of a selection and returns them.
+pub(crate) fn cut(nbs: &mut Nbs, selection: &Selection) -> Fragment {
+    let layers = selected_layers(nbs, selection);
+    let layers: Vec<BTreeMap<Tick, Note>> = nbs.noteblocks.layers[layers]
+        .iter_mut()
+        .map(|layer| {
+            let ticks: Vec<Tick> = layer
+                .notes
+                .range(selection.tick_range())
+                .map(|(&tick, _)| tick)
+                .collect();
+            ticks
+                .into_iter()
+                .filter_map(|tick| {
+                    let note = layer.notes.remove(&tick)?;
+                    Some((tick.saturating_sub(selection.ticks.start), note))
+                })
+                .collect()
+        })
+        .collect();
+    fragment(nbs, selection, layers)
+}
+
+/// Returns the part of the selected layers that exists.
+fn selected_layers(nbs: &Nbs, selection: &Selection) -> Range<usize> {
+    let count = nbs.noteblocks.layers.len();
+    selection.layers.start.min(count)..selection.layers.end.min(count)
+}
+
+fn fragment(nbs: &Nbs, selection: &Selection, layers: Vec<BTreeMap<Tick, Note>>) -> Fragment {
+    let custom_instruments = nbs
+        .custom_instruments
+        .iter()
+        .filter(|info| {
+            layers
+                .iter()
+                .flat_map(|notes| notes.values())
+                .any(|note| note.instrument == info.instrument)
+        })
+        .cloned()
+        .collect();
+    Fragment {
+        layers,
+        length: selection.length(),
+        custom_instruments,
+        vannila_instrument_count: nbs.header.vannila_instrument_count().unwrap_or(16),
+    }
+}
+
+/// Returns the ids of the custom instruments of a fragment the song already has, and the instruments it is missing.
+pub(crate) fn match_instruments<'a>(
+    nbs: &Nbs,
+    fragment: &'a Fragment,
+) -> (
+    HashMap<Instrument, Instrument>,
+    Vec<&'a CustomInstrumentInfo>,
+) {
+    let mut instruments = HashMap::new();
+    let mut missing = Vec::new();
+    for info in &fragment.custom_instruments {
+        // A song only has one tempo changer, no matter its sound.
+        let existing = nbs.custom_instruments.iter().find(|existing| {
+            (existing.name == info.name
+                && existing.file_name == info.file_name
+                && existing.pitch == info.pitch)
+                || (existing.is_tempo_changer() && info.is_tempo_changer())
+        });
+        match existing {
+            Some(existing) => {
+                instruments.insert(info.instrument, existing.instrument);
+            }
+            None => missing.push(info),
+        }
+    }
+    (instruments, missing)
+}
+
+/// Fails if the song has no room for `added` more custom instruments.
+pub(crate) fn check_instrument_room(nbs: &Nbs, added: usize) -> Result<(), NbsError> {
+    let vannila_instrument_count = nbs.header.vannila_instrument_count().unwrap_or(16);
+    let count = nbs.custom_instruments.iter().count() + added;
+    // The ids of the custom instruments follow the vanilla ones, the last id has to fit into a byte.
+    if count > 0 && u8::try_from(vannila_instrument_count as usize + count - 1).is_err() {
+        return Err(NbsError::CountOutOfRange {
+            field: "instrument_count",
+            count,
+        });
+    }
+    Ok(())
+}
+
+/// Pastes a fragment with its first tick at `tick` and its first layer at `layer`.
+pub(crate) fn paste(
+    nbs: &mut Nbs,
+    fragment: &Fragment,
+    tick: Tick,
+    layer: usize,
+    mode: PasteMode,
+) -> Result<PasteReport, NbsError> {
+    let mut report = PasteReport::default();
+    let format = nbs.format();
+    let vannila_instrument_count = nbs.header.vannila_instrument_count().unwrap_or(16);
+    // Find or add the custom instruments first, so a full list of instruments changes nothing.
+    let (mut instruments, added) = match_instruments(nbs, fragment);
+    check_instrument_room(nbs, added.len())?;
+    for info in added {
+        let instrument = nbs
+            .custom_instruments
+            .add(vannila_instrument_count, info.clone())?;
+        instruments.insert(info.instrument, instrument);
+        report.added_instruments += 1;
+    }
+
+    if mode == PasteMode::Insert {
+        for target in &mut nbs.noteblocks.layers {
+            let moved = target.notes.split_off(&tick);
+            for (t, note) in moved {
+                target.notes.insert(t.saturating_add(fragment.length), note);
+            }
+        }
+        if let Some(loop_start_tick) = &mut nbs.header.loop_start_tick {
+            if *loop_start_tick >= tick {
+                *loop_start_tick = loop_start_tick.saturating_add(fragment.length);
+            }
+        }
+    }
+    let layers = layer + fragment.layers.len();
+    while nbs.noteblocks.layers.len() < layers {
+        nbs.noteblocks.layers.push(Layer::from_format(format));
+        report.added_layers += 1;
+    }
+    for (target, notes) in nbs.noteblocks.layers[layer..]
+        .iter_mut()
+        .zip(&fragment.layers)
+    {
+        for (&t, note) in notes {
+            let mut note = note.clone();
+            note.instrument = match instruments.get(&note.instrument) {
+                Some(&instrument) => instrument,
+                None => note
+                    .instrument
+                    .rebase(fragment.vannila_instrument_count, vannila_instrument_count),
+            };
+            if let Instrument::Vanilla(id) = note.instrument {
+                if id >= vannila_instrument_count {
+                    note.instrument = note.instrument.closest_classic();
+                    report.conversion.replaced_instruments += 1;
+                }
+            }
+            note.convert_to(format, &mut report.conversion);
+            if target.notes.insert(tick.saturating_add(t), note).is_some() {
+                report.replaced += 1;
+            }
+        }
+    }
+    nbs.sync_header();
+    Ok(report)
+}
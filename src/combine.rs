@@ -0,0 +1,262 @@
+//! Combining songs into one, like chaining the songs of a playlist.
+//!
+//! `Nbs::append` plays another song after the end of a song, `Nbs::overlay` plays it at the same time in new layers.
+//! When the tempo of the other song differs, it is either stretched to the tempo of the song,
+//! or (when appending) a "Tempo Changer" note switches to its tempo where it starts.
+//!
+//! ## Example: Chaining two songs
+//!
+//! ```rust
+//! use nbs::{
+//!     combine::TempoMode,
+//!     stretch::{Collision, Rounding},
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let mut playlist = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let mut next = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! next.header.song_tempo = 500;
+//! let tempo = TempoMode::Stretch {
+//!     rounding: Rounding::Nearest,
+//!     collision: Collision::KeepLast,
+//! };
+//! let report = playlist.append(&next, tempo).unwrap();
+//! assert!(report.stretch.is_some());
+//! ```
+
+use crate::{
+    clipboard::{self, PasteMode, PasteReport, Selection},
+    noteblocks::{
+        instrument::{CustomInstrumentInfo, Instrument},
+        layer::Layer,
+        note::Note,
+        value::Key,
+    },
+    stretch::{self, Collision, Rounding, Stretch, StretchReport},
+    tempo::{ticks_per_second_to_bpm, MIN_TICKS_PER_SECOND},
+    Nbs, NbsError, Tick,
+};
+
+/// How a song with a different tempo is combined with another song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TempoMode {
+    /// The other song is stretched to the tempo of the song, see the `stretch` module.
+    Stretch {
+        rounding: Rounding,
+        collision: Collision,
+    },
+    /// The ticks of the other song are kept and a "Tempo Changer" note switches to its tempo where it starts.
+    /// This needs version 4 of the new format or later and only works when appending.
+    TempoChanger,
+}
+
+/// Describes what `Nbs::append` and `Nbs::overlay` changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CombineReport {
+    /// How the notes of the other song were pasted.
+    pub paste: PasteReport,
+    /// How the other song was stretched, if its tempo differs.
+    pub stretch: Option<StretchReport>,
+    /// Whether a tempo changer was inserted where the other song starts.
+    pub tempo_changer: bool,
+}
+
+/// Returns the amount of ticks of a song, counting the tick of the last note.
+fn length(nbs: &Nbs) -> Tick {
+    if nbs
+        .noteblocks
+        .layers
+        .iter()
+        .all(|layer| layer.notes.is_empty())
+    {
+        0
+    } else {
+        nbs.song_ticks().saturating_add(1)
+    }
+}
+
+/// Adds `name` to a comma separated list of names, unless it is already part of it.
+fn join(names: &mut String, name: &str) {
+    if name.is_empty() || names.split(", ").any(|n| n == name) {
+        return;
+    }
+    if !names.is_empty() {
+        names.push_str(", ");
+    }
+    names.push_str(name);
+}
+
+pub(crate) fn append(
+    nbs: &mut Nbs,
+    other: &Nbs,
+    tempo: TempoMode,
+) -> Result<CombineReport, NbsError> {
+    let tick = length(nbs);
+    combine(nbs, other, tick, false, tempo)
+}
+
+pub(crate) fn overlay(
+    nbs: &mut Nbs,
+    other: &Nbs,
+    offset: Tick,
+    tempo: TempoMode,
+) -> Result<CombineReport, NbsError> {
+    combine(nbs, other, offset, true, tempo)
+}
+
+/// Pastes all notes of `other` at `tick`, in new layers if `overlay` is set.
+fn combine(
+    nbs: &mut Nbs,
+    other: &Nbs,
+    tick: Tick,
+    overlay: bool,
+    tempo: TempoMode,
+) -> Result<CombineReport, NbsError> {
+    let mut report = CombineReport::default();
+    let other_ticks_per_second = (other.header.song_tempo as f64 / 100.0).max(MIN_TICKS_PER_SECOND);
+    // An empty song has nothing to keep in time with, so it takes the tempo of the other song.
+    let empty = length(nbs) == 0;
+    let ticks_per_second = if empty {
+        other_ticks_per_second
+    } else {
+        nbs.tempo_map().ticks_per_second(tick)
+    };
+    let mut fragment = other.copy(&Selection::new(
+        0..length(other),
+        0..other.noteblocks.layers.len(),
+    ));
+    let mut needs_tempo_changer = false;
+    if (ticks_per_second * 100.0).round() != other.header.song_tempo as f64 {
+        match tempo {
+            TempoMode::Stretch {
+                rounding,
+                collision,
+            } => {
+                let stretch = Stretch {
+                    factor: ticks_per_second / other_ticks_per_second,
+                    rounding,
+                    collision,
+                };
+                let mut layers: Vec<_> = fragment.layers.iter_mut().collect();
+                let other_tempo_changer = other.custom_instruments.tempo_changer();
+                report.stretch = Some(stretch::stretch_notes(
+                    &mut layers,
+                    &stretch,
+                    other_tempo_changer,
+                )?);
+                fragment.length = stretch.tick(fragment.length).0;
+            }
+            TempoMode::TempoChanger if overlay => {
+                return Err(NbsError::InvalidData(
+                    "songs with a different tempo have to be stretched to be overlaid",
+                ))
+            }
+            TempoMode::TempoChanger if nbs.format().version() < 4 => {
+                return Err(NbsError::InvalidData(
+                    "tempo changers need version 4 of the new format or later",
+                ))
+            }
+            TempoMode::TempoChanger => needs_tempo_changer = true,
+        }
+    }
+    // Pasting can only fail when there is no room for the instruments, which is checked before the song is changed.
+    // A tempo changer of the other song is matched to the one that is added.
+    let (_, missing) = clipboard::match_instruments(nbs, &fragment);
+    let adds_tempo_changer = needs_tempo_changer
+        && nbs.custom_instruments.tempo_changer().is_none()
+        && !missing.iter().any(|info| info.is_tempo_changer());
+    clipboard::check_instrument_room(nbs, missing.len() + usize::from(adds_tempo_changer))?;
+
+    if empty {
+        nbs.header.song_tempo = other.header.song_tempo;
+    }
+    let tempo_changer = if needs_tempo_changer {
+        Some(add_tempo_changer(nbs)?)
+    } else {
+        None
+    };
+
+    let first_layer = if overlay {
+        nbs.noteblocks.layers.len()
+    } else {
+        0
+    };
+    let layer_count = nbs.noteblocks.layers.len();
+    report.paste = clipboard::paste(nbs, &fragment, tick, first_layer, PasteMode::Overwrite)?;
+    // Added layers take the settings of the layers of the other song.
+    let format = nbs.format();
+    let layers = nbs.noteblocks.layers[first_layer..].iter_mut().enumerate();
+    for (index, layer) in layers.skip(layer_count - first_layer) {
+        let mut settings = other.noteblocks.layers[index].with_settings();
+        settings.convert_to(format, &mut report.paste.conversion);
+        settings.notes = std::mem::take(&mut layer.notes);
+        *layer = settings;
+    }
+
+    if let Some(instrument) = tempo_changer {
+        // A pitch of 0 would turn the tempo change off.
+        let bpm = ticks_per_second_to_bpm(other_ticks_per_second)
+            .round()
+            .max(1.0);
+        let mut note = Note::new(instrument, Key::default(), None, None, Some(bpm as i16));
+        note.convert_to(format, &mut report.paste.conversion);
+        let free = nbs
+            .noteblocks
+            .layers
+            .iter_mut()
+            .find(|layer| !layer.notes.contains_key(&tick));
+        match free {
+            Some(layer) => {
+                layer.notes.insert(tick, note);
+            }
+            None => {
+                let mut layer = Layer::from_format(format);
+                layer.notes.insert(tick, note);
+                nbs.noteblocks.layers.push(layer);
+                report.paste.added_layers += 1;
+            }
+        }
+        report.tempo_changer = true;
+    }
+
+    let header = &mut nbs.header;
+    join(&mut header.song_author, &other.header.song_author);
+    join(
+        &mut header.original_song_author,
+        &other.header.original_song_author,
+    );
+    header.minutes_spent = header
+        .minutes_spent
+        .saturating_add(other.header.minutes_spent);
+    header.left_clicks = header.left_clicks.saturating_add(other.header.left_clicks);
+    header.right_clicks = header
+        .right_clicks
+        .saturating_add(other.header.right_clicks);
+    header.noteblocks_added = header
+        .noteblocks_added
+        .saturating_add(other.header.noteblocks_added);
+    header.noteblocks_removed = header
+        .noteblocks_removed
+        .saturating_add(other.header.noteblocks_removed);
+    nbs.sync_header();
+    Ok(report)
+}
+
+/// Returns the tempo changer of the song, adding one if it has none.
+fn add_tempo_changer(nbs: &mut Nbs) -> Result<Instrument, NbsError> {
+    if let Some(instrument) = nbs.custom_instruments.tempo_changer() {
+        return Ok(instrument);
+    }
+    let vannila_instrument_count = nbs.header.vannila_instrument_count().unwrap_or(16);
+    nbs.custom_instruments.add(
+        vannila_instrument_count,
+        CustomInstrumentInfo {
+            instrument: Instrument::Custom(0),
+            name: String::from(CustomInstrumentInfo::TEMPO_CHANGER),
+            file_name: String::new(),
+            pitch: Key::default(),
+            press_key: false,
+        },
+    )
+}
@@ -0,0 +1,47 @@
+//! Re-targeting songs to a different NBS format.
+//!
+//! ## Example: Saving a song in the classic format
+//!
+//! ```rust
+//! use nbs::{Nbs, NbsFormat};
+//! use std::fs::File;
+//!
+//! let mut nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let report = nbs.convert_to(NbsFormat::NoteBlockStudio);
+//! if !report.is_lossless() {
+//!     println!("Some information was lost: {:?}", report);
+//! }
+//! let mut buffer = Vec::new();
+//! nbs.encode(&mut buffer).unwrap();
+//! ```
+
+/// Describes which information was lost by converting a song to another format.
+/// Values that are equal to their default (like a velocity of 100) are not counted, since dropping them changes nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversionReport {
+    /// Amount of notes whose velocity was discarded.
+    pub velocity: usize,
+    /// Amount of notes whose panning was discarded.
+    pub panning: usize,
+    /// Amount of notes whose fine pitch was folded into the key, losing the cents in between two keys.
+    pub fine_pitch: usize,
+    /// Amount of notes whose key had to be clamped to 0-87 after folding in their fine pitch.
+    pub clamped_keys: usize,
+    /// Amount of notes whose instrument does not exist in the format and was replaced by the most similar one.
+    pub replaced_instruments: usize,
+    /// Amount of layers whose lock was discarded.
+    pub layer_locks: usize,
+    /// Amount of layers whose solo status was discarded.
+    pub layer_solos: usize,
+    /// Amount of layers whose stereo position was discarded.
+    pub layer_stereo: usize,
+    /// Whether the loop settings were discarded.
+    pub loop_settings: bool,
+}
+
+impl ConversionReport {
+    /// Returns true if no information was lost.
+    pub fn is_lossless(&self) -> bool {
+        *self == ConversionReport::default()
+    }
+}
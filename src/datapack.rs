@@ -0,0 +1,253 @@
+//! Export of songs as Minecraft datapacks that play them with commands.
+//!
+//! Every tick with notes becomes a function that plays its notes to every player with `/playsound`, and schedules the function of the next tick.
+//! The song is started with `/function <namespace>:play` and stopped with `/function <namespace>:stop`.
+//!
+//! Minecraft runs 20 game ticks per second, so every tick is played at the nearest game tick.
+//! The volume is the velocity of the note times the volume of its layer, and the panning moves the sound to the left or right of the player.
+//! Minecraft only changes the pitch of a sound by up to one octave, keys further away from the key of the instrument are clamped.
+//! Custom instruments play sounds that have to be provided by a resource pack, see [`custom_sound_id`].
+//!
+//! ## Example: Exporting a datapack
+//!
+//! ```rust
+//! use nbs::{
+//!     datapack::{self, DatapackOptions},
+//!     noteblocks::instrument,
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let mut options = DatapackOptions::new("my_song");
+//! options.set_sound(instrument::BIT, "minecraft:entity.experience_orb.pickup");
+//! let mut zip = Vec::new();
+//! datapack::encode(&nbs, &options, &mut zip).unwrap();
+//! ```
+
+use crate::{
+    archive::ZipWriter,
+    noteblocks::instrument::{CustomInstrumentInfo, Instrument},
+    render::BASE_KEY,
+    vanilla, Nbs, NbsError, Tick,
+};
+use std::{collections::HashMap, fmt::Write as _, io::Write};
+
+/// The distance in blocks at which fully panned sounds are played to the side of the player.
+const STEREO_DISTANCE: f64 = 2.0;
+
+/// Options for exporting a datapack.
+#[derive(Debug, Clone)]
+pub struct DatapackOptions {
+    /// The namespace of the functions, made of `a-z`, `0-9`, `_`, `-` and `.`.
+    pub namespace: String,
+    /// The `pack_format` of `pack.mcmeta`, which decides the Minecraft versions the datapack works in.
+    /// Functions are placed in the `function` folder from 45 onwards (Minecraft 1.21), and in `functions` before.
+    pub pack_format: u32,
+    /// The description shown in the list of datapacks.
+    pub description: String,
+    /// The sound category whose volume slider applies to the song, like `record` or `master`.
+    pub source: String,
+    /// The namespace of the sounds of custom instruments.
+    pub sound_namespace: String,
+    sounds: HashMap<Instrument, String>,
+}
+
+impl DatapackOptions {
+    pub fn new(namespace: &str) -> Self {
+        DatapackOptions {
+            namespace: namespace.to_string(),
+            pack_format: 48,
+            description: String::new(),
+            source: "record".to_string(),
+            sound_namespace: "nbs".to_string(),
+            sounds: HashMap::new(),
+        }
+    }
+
+    /// Plays the given sound event for an instrument, instead of its note block sound or the sound derived from its file name.
+    pub fn set_sound(&mut self, instrument: Instrument, sound: &str) {
+        self.sounds.insert(instrument, sound.to_string());
+    }
+
+    /// Returns the sound event an instrument plays, or `None` if it is an unknown custom instrument.
+    pub fn sound(&self, nbs: &Nbs, instrument: Instrument) -> Option<String> {
+        if let Some(sound) = self.sounds.get(&instrument) {
+            return Some(sound.clone());
+        }
+        if let Some(name) = vanilla::instrument_name(instrument) {
+            return Some(format!("minecraft:block.note_block.{}", name));
+        }
+        let info = nbs.custom_instruments.get(instrument)?;
+        Some(custom_sound_id(&self.sound_namespace, info))
+    }
+}
+
+impl Default for DatapackOptions {
+    fn default() -> Self {
+        DatapackOptions::new("nbs")
+    }
+}
+
+/// Returns the sound event of a custom instrument, which is its file name without the extension in the given namespace.
+/// Characters that are not allowed in sound events are replaced with `_`, for example `Drums\Kick 1.ogg` becomes `<namespace>:drums/kick_1`.
+pub fn custom_sound_id(namespace: &str, info: &CustomInstrumentInfo) -> String {
+    let path = info.file_name.replace('\\', "/");
+    let path = match path.rfind('.') {
+        Some(dot) if !path[dot..].contains('/') => &path[..dot],
+        _ => &path[..],
+    };
+    let path: String = path
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '-' | '.' | '/' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("{}:{}", namespace, path)
+}
+
+/// Formats a number with up to 4 decimals, without trailing zeros.
+fn number(value: f64) -> String {
+    let formatted = format!("{:.4}", value);
+    let formatted = formatted.trim_end_matches('0').trim_end_matches('.');
+    match formatted {
+        "-0" => "0".to_string(),
+        formatted => formatted.to_string(),
+    }
+}
+
+/// Escapes a string for a JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Returns the command that runs a function after the given amount of game ticks.
+fn call(function: &str, delay: u64) -> String {
+    match delay {
+        0 => format!("function {}\n", function),
+        delay => format!("schedule function {} {}t\n", function, delay),
+    }
+}
+
+/// Encode the song as a datapack zip archive.
+/// Notes of the Tempo Changer and of instruments that do not exist in the song are skipped.
+pub fn encode<W>(nbs: &Nbs, options: &DatapackOptions, writer: &mut W) -> Result<(), NbsError>
+where
+    W: Write,
+{
+    let namespace = &options.namespace;
+    if namespace.is_empty()
+        || !namespace
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '-' | '.'))
+    {
+        return Err(NbsError::InvalidData("invalid datapack namespace"));
+    }
+    let folder = if options.pack_format >= 45 {
+        "function"
+    } else {
+        "functions"
+    };
+    let tempo_map = nbs.tempo_map();
+    let tempo_changer = nbs.custom_instruments.tempo_changer();
+
+    // The game tick and the commands of every tick with sounds.
+    let mut ticks: Vec<(Tick, u64, String)> = Vec::new();
+    for (tick, notes) in nbs.noteblocks.columns() {
+        if tick < 0 {
+            continue;
+        }
+        let mut commands = String::new();
+        for (layer, note) in notes {
+            if Some(note.instrument) == tempo_changer {
+                continue;
+            }
+            let sound = match options.sound(nbs, note.instrument) {
+                Some(sound) => sound,
+                None => continue,
+            };
+            let layer = &nbs.noteblocks.layers[layer];
+            let volume = note.velocity.unwrap_or_default().get() as f64 / 100.0
+                * layer.volume.get() as f64
+                / 100.0;
+            if volume <= 0.0 {
+                continue;
+            }
+            let base_key = match nbs.custom_instruments.get(note.instrument) {
+                Some(info) => info.pitch,
+                None => BASE_KEY,
+            };
+            let semitones = (note.key.get() as f64 - base_key.get() as f64)
+                + note.pitch.unwrap_or(0) as f64 / 100.0;
+            // Panning ranges from 0 (left) to 200 (right), the note and layer panning are averaged.
+            let note_panning = note.panning.unwrap_or_default().get() as f64;
+            let layer_panning = layer.stereo.unwrap_or_default().get() as f64;
+            let pan = ((note_panning + layer_panning) / 2.0 - 100.0) / 100.0;
+            // Local coordinates point to the left of the player.
+            let side = if pan == 0.0 {
+                String::new()
+            } else {
+                number(-pan * STEREO_DISTANCE)
+            };
+            let _ = writeln!(
+                commands,
+                "execute as @a at @s run playsound {} {} @s ^{} ^ ^ {} {}",
+                sound,
+                options.source,
+                side,
+                number(volume),
+                number(2f64.powf(semitones / 12.0)),
+            );
+        }
+        if !commands.is_empty() {
+            let game_tick = (tempo_map.tick_to_seconds(tick as f64) * 20.0).round() as u64;
+            ticks.push((tick, game_tick, commands));
+        }
+    }
+
+    let function = |tick: Tick| format!("{}:tick/{}", namespace, tick);
+    for index in 1..ticks.len() {
+        let (next, next_game_tick, _) = ticks[index];
+        let delay = next_game_tick - ticks[index - 1].1;
+        ticks[index - 1].2.push_str(&call(&function(next), delay));
+    }
+    let path = |name: &str| format!("data/{}/{}/{}.mcfunction", namespace, folder, name);
+    let mut zip = ZipWriter::new(writer);
+    let pack = format!(
+        "{{\n  \"pack\": {{\n    \"pack_format\": {},\n    \"description\": {}\n  }}\n}}\n",
+        options.pack_format,
+        json_string(&options.description)
+    );
+    zip.add("pack.mcmeta", pack.as_bytes())?;
+
+    let mut play = format!("function {}:stop\n", namespace);
+    if let Some(&(tick, game_tick, _)) = ticks.first() {
+        play.push_str(&call(&function(tick), game_tick));
+    }
+    zip.add(&path("play"), play.as_bytes())?;
+    let stop: String = ticks
+        .iter()
+        .map(|(tick, _, _)| format!("schedule clear {}\n", function(*tick)))
+        .collect();
+    zip.add(&path("stop"), stop.as_bytes())?;
+
+    for (tick, _, commands) in &ticks {
+        zip.add(&path(&format!("tick/{}", tick)), commands.as_bytes())?;
+    }
+    zip.finish()?;
+    Ok(())
+}
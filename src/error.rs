@@ -1,3 +1,4 @@
+use crate::Tick;
 use std::{
     error::Error,
     fmt::{self, Display},
@@ -5,14 +6,181 @@ use std::{
     string::FromUtf8Error,
 };
 
+/// A limit enforced while decoding, see `DecodeLimits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    StringLength,
+    Layers,
+    Ticks,
+    CustomInstruments,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::StringLength => write!(f, "string length"),
+            Limit::Layers => write!(f, "amount of layers"),
+            Limit::Ticks => write!(f, "tick"),
+            Limit::CustomInstruments => write!(f, "amount of custom instruments"),
+        }
+    }
+}
+
+/// A section of a NBS file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Header,
+    NoteBlocks,
+    Layers,
+    CustomInstruments,
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Section::Header => write!(f, "header"),
+            Section::NoteBlocks => write!(f, "note blocks"),
+            Section::Layers => write!(f, "layers"),
+            Section::CustomInstruments => write!(f, "custom instruments"),
+        }
+    }
+}
+
+/// Describes where in a file decoding failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeContext {
+    /// The byte offset at which the field starts.
+    pub offset: u64,
+    /// The section the field belongs to.
+    pub section: Section,
+    /// The name of the field that was being read.
+    pub field: &'static str,
+    /// The tick of the note that was being read.
+    pub tick: Option<Tick>,
+    /// The layer of the note or layer information that was being read.
+    pub layer: Option<i16>,
+    /// The index of the custom instrument that was being read.
+    pub instrument: Option<u8>,
+}
+
+impl Display for DecodeContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` in the {} section at byte {}",
+            self.field, self.section, self.offset
+        )?;
+        if let Some(tick) = self.tick {
+            write!(f, ", tick {}", tick)?;
+        }
+        if let Some(layer) = self.layer {
+            write!(f, ", layer {}", layer)?;
+        }
+        if let Some(instrument) = self.instrument {
+            write!(f, ", custom instrument {}", instrument)?;
+        }
+        Ok(())
+    }
+}
+
+/// A repair made while decoding leniently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repair {
+    /// Layers were added, because a note was placed in a layer beyond the layer count.
+    AddedLayers(usize),
+    /// The note blocks ended early, the remaining notes are missing.
+    TruncatedNoteBlocks,
+    /// The layer information ended early, the remaining layers with notes use default values and empty ones are left out.
+    MissingLayerInfo,
+    /// The custom instruments ended early, only the instruments before that point are kept.
+    MissingCustomInstruments,
+    /// A string was not valid UTF-8, invalid sequences were replaced with `U+FFFD`.
+    LossyString,
+}
+
+impl Display for Repair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Repair::AddedLayers(count) => write!(f, "added {} missing layer(s)", count),
+            Repair::TruncatedNoteBlocks => write!(f, "the note blocks are truncated"),
+            Repair::MissingLayerInfo => {
+                write!(f, "the layer information is missing, using default values")
+            }
+            Repair::MissingCustomInstruments => write!(f, "the custom instruments are missing"),
+            Repair::LossyString => write!(f, "replaced invalid UTF-8"),
+        }
+    }
+}
+
+/// Describes a repair made while decoding leniently, and where it was made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeWarning {
+    pub context: DecodeContext,
+    pub repair: Repair,
+}
+
+impl Display for DecodeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Repaired {}; {}", self.context, self.repair)
+    }
+}
+
 #[derive(Debug)]
 pub enum NbsError {
     /// This error occures when the format does not contain the expected data
     InvalidFormat,
+    /// This error occurs when the file uses a version of the new format that is not supported yet
+    UnsupportedVersion(i8),
+    /// This error occurs when the data contradicts itself, for example a note in a layer that does not exist
+    InvalidData(&'static str),
+    /// This error occurs when a decoded value exceeds one of the configured limits
+    LimitExceeded { limit: Limit, value: usize },
     /// This error occurs when decoding a string thats not utf-8
     InvalidString(FromUtf8Error),
     /// This error occures when an io operation fails
     IoError(io::Error),
+    /// This error occurs when encoding a tick that does not fit into the 16-bit fields of the format
+    TickOutOfRange { field: &'static str, tick: Tick },
+    /// This error occurs when encoding more items than the count field of the format can store
+    CountOutOfRange { field: &'static str, count: usize },
+    /// This error occurs when transposing a note would move its key outside of 0-87
+    KeyOutOfRange { tick: Tick, key: i32 },
+    /// This error occurs when stretching a song moves two notes of a layer onto the same tick
+    NoteCollision { tick: Tick, layer: usize },
+    /// This error occurs when decoding fails, it describes where the `source` error occurred
+    Decode {
+        context: DecodeContext,
+        source: Box<NbsError>,
+    },
+}
+
+impl NbsError {
+    /// Returns where decoding failed, if this error occurred while decoding.
+    ///
+    /// ```rust
+    /// use nbs::{error::Section, Nbs};
+    ///
+    /// let mut buffer = std::fs::read("tests/1.nbs").unwrap();
+    /// buffer.truncate(100); // Cut the file off in the middle of the note blocks.
+    /// let error = Nbs::decode(&mut &buffer[..]).err().unwrap();
+    /// let context = error.context().unwrap();
+    /// assert_eq!(context.section, Section::NoteBlocks);
+    /// println!("{}", error); // Failed to decode `instrument` in the note blocks section at byte 100, tick 0, layer 7; ...
+    /// ```
+    pub fn context(&self) -> Option<&DecodeContext> {
+        match self {
+            NbsError::Decode { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Returns the error without the decode context.
+    pub fn without_context(&self) -> &NbsError {
+        match self {
+            NbsError::Decode { source, .. } => source.without_context(),
+            e => e,
+        }
+    }
 }
 
 impl From<io::Error> for NbsError {
@@ -33,8 +201,41 @@ impl Display for NbsError {
             NbsError::InvalidFormat => {
                 write!(f, "The target format is not supported by the given data.")
             }
+            NbsError::UnsupportedVersion(v) => write!(
+                f,
+                "NBS version {} is not supported, the latest supported version is {}.",
+                v,
+                crate::NbsFormat::LATEST_VERSION
+            ),
+            NbsError::InvalidData(reason) => write!(f, "Invalid data; {}", reason),
+            NbsError::LimitExceeded { limit, value } => {
+                write!(f, "The {} exceeds the limit; found {}", limit, value)
+            }
+            NbsError::TickOutOfRange { field, tick } => write!(
+                f,
+                "Tick {} does not fit into the 16-bit `{}` field of the format.",
+                tick, field
+            ),
+            NbsError::CountOutOfRange { field, count } => write!(
+                f,
+                "{} items do not fit into the `{}` field of the format.",
+                count, field
+            ),
+            NbsError::KeyOutOfRange { tick, key } => write!(
+                f,
+                "The note at tick {} would be moved to key {}, outside of 0-87.",
+                tick, key
+            ),
+            NbsError::NoteCollision { tick, layer } => write!(
+                f,
+                "Two notes of layer {} would be moved onto tick {}.",
+                layer, tick
+            ),
             NbsError::InvalidString(e) => write!(f, "Failed to decode string; {}", e),
             NbsError::IoError(e) => write!(f, "{}", e),
+            NbsError::Decode { context, source } => {
+                write!(f, "Failed to decode {}; {}", context, source)
+            }
         }
     }
 }
@@ -43,8 +244,16 @@ impl Error for NbsError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             NbsError::InvalidFormat => None,
+            NbsError::UnsupportedVersion(_) => None,
+            NbsError::InvalidData(_) => None,
+            NbsError::LimitExceeded { .. } => None,
+            NbsError::TickOutOfRange { .. } => None,
+            NbsError::CountOutOfRange { .. } => None,
+            NbsError::KeyOutOfRange { .. } => None,
+            NbsError::NoteCollision { .. } => None,
             NbsError::InvalidString(e) => Some(e),
             NbsError::IoError(e) => Some(e),
+            NbsError::Decode { source, .. } => Some(source.as_ref()),
         }
     }
     fn description(&self) -> &str {
@@ -1,24 +1,32 @@
-use crate::{NbsError, NbsFormat};
-use byteorder::LittleEndian;
+use crate::{
+    conversion::ConversionReport,
+    error::{Limit, Section},
+    io::{self, DecodeLimits, Decoder},
+    tempo::TempoMap,
+    NbsError, NbsFormat, Tick,
+};
+use byteorder::{LittleEndian, ReadBytesExt};
 use std::time::Duration;
 
 /// The header contains information about the file
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     /// The first 2 bytes are always zero in the new fromat.
     /// In the old NBS format, this used to be song length, which can never be zero.
-    pub(crate) old_song_length: i16,
+    pub(crate) old_song_length: Tick,
     /// The version of the new NBS format.
     /// Only avabile in the new format.
     pub(crate) version_number: Option<i8>,
     /// Amount of default instruments when the song was saved.
     /// This is needed to determine at what index custom instruments start.
     /// Only avabile in the new format
-    pub vannila_instrument_count: Option<i8>,
+    pub vannila_instrument_count: Option<u8>,
     /// The length of the song, measured in ticks.
     /// Divide this by the tempo to get the length of the song in seconds.
     /// Only avabile in the new format starting from version 3.
-    pub(crate) song_length: Option<i16>,
+    /// Up to version 4 this is the last tick of the song, since version 5 it is the amount of ticks.
+    pub(crate) song_length: Option<Tick>,
     /// The last layer with at least one note block in it, or the last layer that has had its name, volume or stereo changed.
     pub layer_count: i16,
     /// The name of the song.
@@ -60,7 +68,7 @@ pub struct Header {
     pub max_loop_count: Option<i8>,
     /// Determines which part of the song (in ticks) it loops back to.
     /// Only avabile in the new format.
-    pub loop_start_tick: Option<i16>,
+    pub loop_start_tick: Option<Tick>,
     /// Not part of the Header.
     pub format: NbsFormat,
 }
@@ -90,7 +98,7 @@ impl Header {
             is_loop: Some(false),
             max_loop_count: Some(0),
             loop_start_tick: Some(0),
-            format: format,
+            format,
         }
     }
 
@@ -98,53 +106,75 @@ impl Header {
     where
         R: crate::ReadStringExt,
     {
-        let old_song_length = reader.read_i16::<LittleEndian>()?;
+        Header::decode_from(&mut Decoder::new(reader, DecodeLimits::default()))
+    }
+
+    pub(crate) fn decode_from<R>(reader: &mut Decoder<R>) -> Result<Self, NbsError>
+    where
+        R: std::io::Read,
+    {
+        reader.enter(Section::Header);
+        let old_song_length = reader.field_tick("old_song_length")?;
         let version = if old_song_length != 0 {
             NbsFormat::NoteBlockStudio
         } else {
-            NbsFormat::OpenNoteBlockStudio(reader.read_i8()?)
+            reader.field("version_number", |r| {
+                let version = NbsFormat::OpenNoteBlockStudio(r.read_i8()?);
+                if version.is_supported() {
+                    Ok(version)
+                } else {
+                    Err(NbsError::UnsupportedVersion(version.version()))
+                }
+            })?
         };
         let version_number = match version {
             NbsFormat::NoteBlockStudio => None,
             NbsFormat::OpenNoteBlockStudio(v) => Some(v),
         };
         let vannila_instrument_count = if version.is_new() {
-            Some(reader.read_i8()?)
+            Some(reader.field_u8("vannila_instrument_count")?)
         } else {
             None
         };
-        let song_length = if version.is_new() {
-            Some(reader.read_i16::<LittleEndian>()?)
+        let song_length = if version.version() >= 3 {
+            Some(reader.field_tick("song_length")?)
         } else {
             None
         };
-        let layer_count = reader.read_i16::<LittleEndian>()?;
-        let song_name = reader.read_string()?;
-        let song_author = reader.read_string()?;
-        let original_song_author = reader.read_string()?;
-        let song_description = reader.read_string()?;
-        let song_tempo = reader.read_i16::<LittleEndian>()?;
-        let auto_saving = if reader.read_i8()? == 1 { true } else { false };
-        let auto_saving_duration = reader.read_i8()?;
-        let time_signature = reader.read_i8()?;
-        let minutes_spent = reader.read_i32::<LittleEndian>()?;
-        let left_clicks = reader.read_i32::<LittleEndian>()?;
-        let right_clicks = reader.read_i32::<LittleEndian>()?;
-        let noteblocks_added = reader.read_i32::<LittleEndian>()?;
-        let noteblocks_removed = reader.read_i32::<LittleEndian>()?;
-        let imported_file_name = reader.read_string()?;
+        let layer_count = reader.field("layer_count", |r| {
+            let layer_count = r.read_i16::<LittleEndian>()?;
+            if layer_count < 0 {
+                return Err(NbsError::InvalidData("negative layer count"));
+            }
+            r.check_limit(Limit::Layers, layer_count as usize)?;
+            Ok(layer_count)
+        })?;
+        let song_name = reader.field_string("song_name")?;
+        let song_author = reader.field_string("song_author")?;
+        let original_song_author = reader.field_string("original_song_author")?;
+        let song_description = reader.field_string("song_description")?;
+        let song_tempo = reader.field_i16("song_tempo")?;
+        let auto_saving = reader.field_i8("auto_saving")? == 1;
+        let auto_saving_duration = reader.field_i8("auto_saving_duration")?;
+        let time_signature = reader.field_i8("time_signature")?;
+        let minutes_spent = reader.field_i32("minutes_spent")?;
+        let left_clicks = reader.field_i32("left_clicks")?;
+        let right_clicks = reader.field_i32("right_clicks")?;
+        let noteblocks_added = reader.field_i32("noteblocks_added")?;
+        let noteblocks_removed = reader.field_i32("noteblocks_removed")?;
+        let imported_file_name = reader.field_string("imported_file_name")?;
         let is_loop = if version.is_new() {
-            Some(if reader.read_i8()? == 1 { true } else { false })
+            Some(reader.field_i8("is_loop")? == 1)
         } else {
             None
         };
         let max_loop_count = if version.is_new() {
-            Some(reader.read_i8()?)
+            Some(reader.field_i8("max_loop_count")?)
         } else {
             None
         };
         let loop_start_tick = if version.is_new() {
-            Some(reader.read_i16::<LittleEndian>()?)
+            Some(reader.field_tick("loop_start_tick")?)
         } else {
             None
         };
@@ -179,16 +209,21 @@ impl Header {
     where
         W: crate::WriteStringExt,
     {
-        writer.write_i16::<LittleEndian>(self.old_song_length)?;
+        if !format.is_supported() {
+            return Err(NbsError::UnsupportedVersion(format.version()));
+        }
+        writer
+            .write_i16::<LittleEndian>(io::encode_tick("old_song_length", self.old_song_length)?)?;
         if format.version() > 0 {
             writer.write_i8(self.version_number.ok_or(NbsError::InvalidFormat)?)?;
-            writer.write_i8(
+            writer.write_u8(
                 self.vannila_instrument_count
                     .ok_or(NbsError::InvalidFormat)?,
             )?;
         }
         if format.version() >= 3 {
-            writer.write_i16::<LittleEndian>(self.song_length.ok_or(NbsError::InvalidFormat)?)?;
+            let song_length = self.song_length.ok_or(NbsError::InvalidFormat)?;
+            writer.write_i16::<LittleEndian>(io::encode_tick("song_length", song_length)?)?;
         }
         writer.write_i16::<LittleEndian>(self.layer_count)?;
         writer.write_string(&self.song_name)?;
@@ -212,14 +247,40 @@ impl Header {
                 0
             })?;
             writer.write_i8(self.max_loop_count.ok_or(NbsError::InvalidFormat)?)?;
+            let loop_start_tick = self.loop_start_tick.ok_or(NbsError::InvalidFormat)?;
             writer
-                .write_i16::<LittleEndian>(self.loop_start_tick.ok_or(NbsError::InvalidFormat)?)?;
+                .write_i16::<LittleEndian>(io::encode_tick("loop_start_tick", loop_start_tick)?)?;
         }
 
         Ok(())
     }
 
-    pub fn vannila_instrument_count(&self) -> Result<i8, NbsError> {
+    /// Fills or drops the fields that depend on the format, recording discarded loop settings in the report.
+    /// The song length is reset and has to be updated afterwards.
+    pub fn convert_to(&mut self, format: NbsFormat, report: &mut ConversionReport) {
+        if format.is_new() {
+            self.old_song_length = 0;
+            self.version_number = Some(format.version());
+            self.vannila_instrument_count.get_or_insert(16);
+            self.song_length = if format.version() >= 3 { Some(0) } else { None };
+            self.is_loop.get_or_insert(false);
+            self.max_loop_count.get_or_insert(0);
+            self.loop_start_tick.get_or_insert(0);
+        } else {
+            self.version_number = None;
+            self.vannila_instrument_count = None;
+            self.song_length = None;
+            if self.is_loop.take() == Some(true)
+                || self.max_loop_count.take().unwrap_or(0) != 0
+                || self.loop_start_tick.take().unwrap_or(0) != 0
+            {
+                report.loop_settings = true;
+            }
+        }
+        self.format = format;
+    }
+
+    pub fn vannila_instrument_count(&self) -> Result<u8, NbsError> {
         Ok(match self.format {
             NbsFormat::NoteBlockStudio => 10,
             NbsFormat::OpenNoteBlockStudio(_) => self
@@ -229,12 +290,15 @@ impl Header {
     }
 
     /// Returns the song ticks.
-    /// This method will only return valid results for old versions and version 3 and 4 of the new version.
-    pub fn song_ticks(&self) -> Result<Option<i16>, NbsError> {
+    /// This method will only return valid results for old versions and version 3 and above of the new version.
+    pub fn song_ticks(&self) -> Result<Option<Tick>, NbsError> {
         Ok(match self.format {
             NbsFormat::NoteBlockStudio => Some(self.old_song_length),
             NbsFormat::OpenNoteBlockStudio(v) => {
-                if v >= 3 {
+                if v >= 5 {
+                    // Version 5 stores the amount of ticks instead of the last tick.
+                    Some((self.song_length.ok_or(NbsError::InvalidFormat)? - 1).max(0))
+                } else if v >= 3 {
                     Some(self.song_length.ok_or(NbsError::InvalidFormat)?)
                 } else {
                     None
@@ -244,14 +308,15 @@ impl Header {
     }
 
     /// Returns the song Duration.
-    /// This method will only return valid results for old versions and version 3 and 4 of the new version.
+    /// This method will only return valid results for old versions and version 3 and above of the new version.
+    /// Tempo changes are not known to the header, `Nbs::song_length` follows them.
     pub fn song_length(&self) -> Result<Option<Duration>, NbsError> {
         let song_ticks = self.song_ticks()?;
         if song_ticks.is_none() {
             return Ok(None);
         }
-        Ok(Some(Duration::from_secs_f32(
-            song_ticks.unwrap() as f32 / (self.song_tempo as f32 / 100.0),
-        )))
+        Ok(Some(
+            TempoMap::new(self.song_tempo as f64 / 100.0).tick_to_time(song_ticks.unwrap()),
+        ))
     }
 }
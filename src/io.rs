@@ -1,13 +1,38 @@
-use crate::error::NbsError;
+use crate::{
+    error::{DecodeContext, DecodeWarning, Limit, NbsError, Repair, Section},
+    Tick,
+};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::io;
+use std::{
+    convert::TryFrom,
+    io::{self, Read},
+};
 
 pub trait ReadStringExt: ReadBytesExt {
+    /// Reads a string prefixed by its length.
+    /// The buffer grows with the data that is actually read, so a bogus length can not cause a huge allocation.
     fn read_string(&mut self) -> Result<String, NbsError> {
+        self.read_string_limited(i32::MAX as usize)
+    }
+
+    /// Reads a string prefixed by its length, failing if the length exceeds `max_length` bytes.
+    fn read_string_limited(&mut self, max_length: usize) -> Result<String, NbsError> {
         let len = self.read_i32::<LittleEndian>()?;
-        let mut buffer: Vec<u8> = Vec::with_capacity(len as usize);
-        buffer.resize(len as usize, 0u8);
-        self.read_exact(&mut buffer)?;
+        if len < 0 {
+            return Err(NbsError::InvalidData("negative string length"));
+        }
+        let len = len as usize;
+        if len > max_length {
+            return Err(NbsError::LimitExceeded {
+                limit: Limit::StringLength,
+                value: len,
+            });
+        }
+        let mut buffer = Vec::new();
+        self.take(len as u64).read_to_end(&mut buffer)?;
+        if buffer.len() != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
         Ok(String::from_utf8(buffer)?)
     }
 }
@@ -22,3 +47,204 @@ pub trait WriteStringExt: WriteBytesExt {
 
 impl<R> ReadStringExt for R where R: ReadBytesExt {}
 impl<W> WriteStringExt for W where W: WriteBytesExt {}
+
+/// Limits that are enforced while decoding, to reject malformed or malicious files early.
+/// The defaults accept everything the format can express, use stricter limits for untrusted files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// The maximum length of a string in bytes.
+    pub max_string_length: usize,
+    /// The maximum amount of layers.
+    pub max_layers: usize,
+    /// The maximum tick a note can be placed at.
+    pub max_ticks: usize,
+    /// The maximum amount of custom instruments.
+    pub max_custom_instruments: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_string_length: i32::MAX as usize,
+            max_layers: i16::MAX as usize,
+            max_ticks: Tick::MAX as usize,
+            max_custom_instruments: u8::MAX as usize,
+        }
+    }
+}
+
+/// Converts a tick to the 16-bit value of a field, failing if it does not fit.
+pub(crate) fn encode_tick(field: &'static str, tick: Tick) -> Result<i16, NbsError> {
+    i16::try_from(tick).map_err(|_| NbsError::TickOutOfRange { field, tick })
+}
+
+/// Wraps the reader while decoding and carries the state shared by all sections of a file.
+/// It keeps track of the position in the file, so errors can be reported with their context.
+pub(crate) struct Decoder<'a, R> {
+    reader: &'a mut R,
+    pub(crate) limits: DecodeLimits,
+    position: u64,
+    section: Section,
+    pub(crate) tick: Option<Tick>,
+    pub(crate) layer: Option<i16>,
+    pub(crate) instrument: Option<u8>,
+    /// Whether damaged data should be repaired instead of failing.
+    pub(crate) lenient: bool,
+    /// The repairs made while decoding leniently.
+    pub(crate) warnings: Vec<DecodeWarning>,
+}
+
+impl<'a, R> Decoder<'a, R>
+where
+    R: Read,
+{
+    pub(crate) fn new(reader: &'a mut R, limits: DecodeLimits) -> Self {
+        Decoder {
+            reader,
+            limits,
+            position: 0,
+            section: Section::Header,
+            tick: None,
+            layer: None,
+            instrument: None,
+            lenient: false,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Creates a decoder that repairs damaged data where possible, see `Nbs::decode_lenient`.
+    pub(crate) fn lenient(reader: &'a mut R, limits: DecodeLimits) -> Self {
+        let mut decoder = Decoder::new(reader, limits);
+        decoder.lenient = true;
+        decoder
+    }
+
+    /// Returns the context of a field starting at `offset`.
+    fn context(&self, field: &'static str, offset: u64) -> DecodeContext {
+        DecodeContext {
+            offset,
+            section: self.section,
+            field,
+            tick: self.tick,
+            layer: self.layer,
+            instrument: self.instrument,
+        }
+    }
+
+    /// Records a repair of the field at the current position.
+    pub(crate) fn warn(&mut self, field: &'static str, repair: Repair) {
+        let context = self.context(field, self.position);
+        self.warnings.push(DecodeWarning { context, repair });
+    }
+
+    /// When decoding leniently, turns an unexpected end of the data into a warning.
+    /// The data decoded so far is kept by the caller.
+    pub(crate) fn recover(
+        &mut self,
+        result: Result<(), NbsError>,
+        repair: Repair,
+    ) -> Result<(), NbsError> {
+        match result {
+            Err(NbsError::Decode { context, source }) if self.lenient => match *source {
+                NbsError::IoError(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    self.warnings.push(DecodeWarning { context, repair });
+                    Ok(())
+                }
+                source => Err(NbsError::Decode {
+                    context,
+                    source: Box::new(source),
+                }),
+            },
+            result => result,
+        }
+    }
+
+    /// Starts decoding a new section.
+    pub(crate) fn enter(&mut self, section: Section) {
+        self.section = section;
+        self.tick = None;
+        self.layer = None;
+        self.instrument = None;
+    }
+
+    /// Decodes a field, adding the context of the field to any error.
+    pub(crate) fn field<T, F>(&mut self, field: &'static str, decode: F) -> Result<T, NbsError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, NbsError>,
+    {
+        let offset = self.position;
+        decode(self).map_err(|e| NbsError::Decode {
+            context: self.context(field, offset),
+            source: Box::new(e),
+        })
+    }
+
+    pub(crate) fn field_u8(&mut self, field: &'static str) -> Result<u8, NbsError> {
+        self.field(field, |r| Ok(r.read_u8()?))
+    }
+
+    pub(crate) fn field_i8(&mut self, field: &'static str) -> Result<i8, NbsError> {
+        self.field(field, |r| Ok(r.read_i8()?))
+    }
+
+    pub(crate) fn field_i16(&mut self, field: &'static str) -> Result<i16, NbsError> {
+        self.field(field, |r| Ok(r.read_i16::<LittleEndian>()?))
+    }
+
+    /// Decodes a 16-bit tick field.
+    pub(crate) fn field_tick(&mut self, field: &'static str) -> Result<Tick, NbsError> {
+        self.field_i16(field).map(Tick::from)
+    }
+
+    pub(crate) fn field_i32(&mut self, field: &'static str) -> Result<i32, NbsError> {
+        self.field(field, |r| Ok(r.read_i32::<LittleEndian>()?))
+    }
+
+    /// Decodes a string field, enforcing the string length limit.
+    /// When decoding leniently, invalid UTF-8 is replaced instead of failing.
+    pub(crate) fn field_string(&mut self, field: &'static str) -> Result<String, NbsError> {
+        let max_length = self.limits.max_string_length;
+        match self.field(field, |r| r.read_string_limited(max_length)) {
+            Err(NbsError::Decode { context, source }) if self.lenient => match *source {
+                NbsError::InvalidString(e) => {
+                    self.warnings.push(DecodeWarning {
+                        context,
+                        repair: Repair::LossyString,
+                    });
+                    Ok(String::from_utf8_lossy(e.as_bytes()).into_owned())
+                }
+                source => Err(NbsError::Decode {
+                    context,
+                    source: Box::new(source),
+                }),
+            },
+            result => result,
+        }
+    }
+
+    /// Fails if `value` exceeds the given limit.
+    pub(crate) fn check_limit(&self, limit: Limit, value: usize) -> Result<(), NbsError> {
+        let max = match limit {
+            Limit::StringLength => self.limits.max_string_length,
+            Limit::Layers => self.limits.max_layers,
+            Limit::Ticks => self.limits.max_ticks,
+            Limit::CustomInstruments => self.limits.max_custom_instruments,
+        };
+        if value > max {
+            Err(NbsError::LimitExceeded { limit, value })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a, R> Read for Decoder<'a, R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
@@ -1,13 +1,17 @@
 //! This crate provides functionality for decoding & encoding (Open)NoteBlockStudio buffers.
 //!
-//! It supports the original NBS format, aswell as version 1-4 of the unofficial new format introduced in [OpenNoteBlockStudio](https://github.com/HielkeMinecraft/OpenNoteBlockStudio).
+//! It supports the original NBS format, aswell as version 1-5 of the unofficial new format introduced in [OpenNoteBlockStudio](https://github.com/HielkeMinecraft/OpenNoteBlockStudio).
 //! Documentation on the NBS format can be found at [NoteBlockStudio](https://www.stuffbydavid.com/mcnbs/format) and [OpenNoteBlockStudio](https://hielkeminecraft.github.io/OpenNoteBlockStudio/nbs).
 //!
 //! ## Example: Editing a NBS file
 //!
 //! ```rust
 //! use nbs::{
-//!     noteblocks::{instrument, note::Note},
+//!     noteblocks::{
+//!         instrument,
+//!         note::Note,
+//!         value::{Key, Panning, Velocity, Volume},
+//!     },
 //!     Nbs,
 //! };
 //! use std::fs::File;
@@ -16,11 +20,17 @@
 //!     let mut file = File::open("tests/1.nbs").unwrap();
 //!     let mut nbs = Nbs::decode(&mut file).unwrap();
 //!     nbs.noteblocks.layers[2].name = String::from("Cows"); // Renaming the 3rd layer "Cows".
-//!     nbs.noteblocks.layers[2].volume = 25; // Setting its volume to 25%.
+//!     nbs.noteblocks.layers[2].volume = Volume::new(25).unwrap(); // Setting its volume to 25%.
 //!     // Insert a Note in the 3rd layer at tick 0
 //!     nbs.noteblocks.layers[2].notes.insert(
 //!         0,
-//!         Note::new(instrument::COW_BELL, 33, Some(100), Some(100), Some(0)),
+//!         Note::new(
+//!             instrument::COW_BELL,
+//!             Key::new(33).unwrap(),
+//!             Some(Velocity::default()),
+//!             Some(Panning::CENTER),
+//!             Some(0),
+//!         ),
 //!     );
 //!     // Write the changes to `out1.nbs`.
 //!     nbs.encode(&mut File::create("out1.nbs").unwrap()).unwrap();
@@ -30,7 +40,10 @@
 //! ```rust
 //! use nbs::{
 //!     header::Header,
-//!     noteblocks::{instrument, instrument::CustomInstruments, layer::Layer, note::Note, NoteBlocks},
+//!     noteblocks::{
+//!         instrument, instrument::CustomInstruments, layer::Layer, note::Note,
+//!         value::{Key, Panning, Velocity}, NoteBlocks,
+//!     },
 //!     Nbs, NbsFormat,
 //! };
 //! use std::fs::File;
@@ -50,37 +63,111 @@
 //!             i,
 //!             Note::new(
 //!                 instrument::PIANO,
-//!                 (33 + i) as i8,
-//!                 Some(100),
-//!                 Some(100),
+//!                 Key::saturating(33 + i),
+//!                 Some(Velocity::default()),
+//!                 Some(Panning::CENTER),
 //!                 Some(0),
 //!             ),
 //!         );
 //!     }
 //!     let custom_instruments = CustomInstruments::new(); // Create a empty list of custom instruments.
 //!     let mut nbs = Nbs::from_componets(header, noteblocks, custom_instruments); // Assamble everything together.
-//!     nbs.update(); // Update certian fields in the header to match the rest of the file.
+//!     nbs.fix(); // Update certian fields in the header to match the rest of the file.
 //!     nbs.encode(&mut file); // save!
 //! }
 //! ```
+//! ## Serde
+//!
+//! With the `serde` feature, the whole song model implements `Serialize` and `Deserialize`.
+//! A song serialized to JSON and deserialized again encodes to exactly the same bytes.
+//! The JSON schema mirrors the structs field by field:
+//!
+//! - The song is an object with `header`, `noteblocks` and `custom_instruments`.
+//! - `header` holds every field of the header by its name, including `old_song_length`, `version_number` and `song_length`,
+//!   and the `format`, which is `"NoteBlockStudio"` or `{"OpenNoteBlockStudio": <version>}`.
+//! - `noteblocks` is an object with `layers`, an array of layers in order.
+//! - A layer holds `name`, `locked`, `solo`, `volume`, `stereo` and `notes`, an object of notes keyed by their tick as a string.
+//! - A note holds `instrument`, `key`, `velocity`, `panning` and `pitch`.
+//!   An instrument is `{"Vanilla": <id>}` or `{"Custom": <id>}`.
+//!   Keys, velocities, pannings and volumes are plain numbers from 0-255.
+//! - `custom_instruments` is an array of objects with `instrument`, `name`, `file_name`, `pitch` and `press_key`.
+//!   They are encoded in the order of the array, so their ids have to follow that order.
+//!
+//! Fields that do not exist in the format of the song are `null`.
+//!
+//! ```rust
+//! # #[cfg(feature = "serde")]
+//! # fn main() {
+//! use nbs::Nbs;
+//!
+//! let data = std::fs::read("tests/1.nbs").unwrap();
+//! let nbs = Nbs::decode(&mut &data[..]).unwrap();
+//! let json = serde_json::to_string(&nbs).unwrap();
+//! let nbs: Nbs = serde_json::from_str(&json).unwrap();
+//! let mut buffer = Vec::new();
+//! nbs.encode(&mut buffer).unwrap();
+//! assert_eq!(buffer, data);
+//! # }
+//! # #[cfg(not(feature = "serde"))]
+//! # fn main() {}
+//! ```
 
-use error::NbsError;
+use clipboard::{Fragment, PasteMode, PasteReport, Selection};
+use combine::{CombineReport, TempoMode};
+use conversion::ConversionReport;
+use error::{DecodeWarning, NbsError};
 use header::Header;
-use io::{ReadStringExt, WriteStringExt};
-use noteblocks::{instrument::CustomInstruments, NoteBlocks};
-use std::time::Duration;
+use io::{DecodeLimits, Decoder, ReadStringExt, WriteStringExt};
+use noteblocks::{instrument::CustomInstruments, layer::Layer, NoteBlocks};
+use std::{convert::TryFrom, ops::Range, time::Duration};
+use stretch::{Stretch, StretchReport};
+use tempo::TempoMap;
+use transpose::{Transpose, TransposeReport};
+use validation::Issue;
 
+mod archive;
+pub mod clipboard;
+pub mod combine;
+pub mod conversion;
+pub mod datapack;
 pub mod error;
 pub mod header;
 pub mod io;
+pub mod midi;
 pub mod noteblocks;
+pub mod player;
+pub mod render;
+pub mod stretch;
+pub mod structure;
+pub mod tempo;
+pub mod transpose;
+pub mod validation;
+pub mod vanilla;
+
+/// A position in a song, measured in ticks.
+/// NBS files store notes with 16-bit jumps between ticks, so songs can be longer than `i16::MAX` ticks.
+/// The song length and loop start of the header are 16-bit though, so long songs can only be encoded in formats without a song length.
+pub type Tick = i32;
 
 #[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NbsFormat {
     NoteBlockStudio,
     OpenNoteBlockStudio(i8),
 }
 impl NbsFormat {
+    /// The latest version of the new format that can be decoded and encoded.
+    pub const LATEST_VERSION: i8 = 5;
+    /// The latest format that can be decoded and encoded.
+    pub const LATEST: NbsFormat = NbsFormat::OpenNoteBlockStudio(NbsFormat::LATEST_VERSION);
+
+    /// Returns false for versions of the new format that are unknown to this crate.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            NbsFormat::NoteBlockStudio => true,
+            &NbsFormat::OpenNoteBlockStudio(v) => (1..=NbsFormat::LATEST_VERSION).contains(&v),
+        }
+    }
     pub fn is_new(&self) -> bool {
         match self {
             NbsFormat::NoteBlockStudio => false,
@@ -95,6 +182,7 @@ impl NbsFormat {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Nbs {
     pub header: Header,
     pub noteblocks: NoteBlocks,
@@ -115,13 +203,23 @@ impl Nbs {
     }
 
     /// Decode a NBS buffer.
-    pub fn decode<R>(mut reader: &mut R) -> Result<Nbs, NbsError>
+    pub fn decode<R>(reader: &mut R) -> Result<Nbs, NbsError>
+    where
+        R: ReadStringExt,
+    {
+        Nbs::decode_with_limits(reader, &DecodeLimits::default())
+    }
+
+    /// Decode a NBS buffer, failing as soon as the data exceeds one of the limits.
+    /// Malformed data never causes a panic or an allocation that is much larger than the data itself.
+    pub fn decode_with_limits<R>(reader: &mut R, limits: &DecodeLimits) -> Result<Nbs, NbsError>
     where
         R: ReadStringExt,
     {
-        let header = Header::decode(&mut reader)?;
-        let noteblocks = NoteBlocks::decode(&mut reader, &header)?;
-        let custom_instruments = CustomInstruments::decode(&mut reader, &header)?;
+        let mut reader = Decoder::new(reader, *limits);
+        let header = Header::decode_from(&mut reader)?;
+        let noteblocks = NoteBlocks::decode_from(&mut reader, &header)?;
+        let custom_instruments = CustomInstruments::decode_from(&mut reader, &header)?;
         Ok(Nbs {
             header,
             noteblocks,
@@ -129,17 +227,131 @@ impl Nbs {
         })
     }
 
+    /// Decode a damaged NBS buffer, repairing what can be repaired instead of failing.
+    /// Truncated sections keep the data read so far, notes in undeclared layers get new layers and invalid strings are replaced lossily.
+    /// Every repair is returned as a warning, errors that can not be repaired (like an unsupported version) still fail.
+    ///
+    /// ```rust
+    /// use nbs::{io::DecodeLimits, Nbs};
+    ///
+    /// let data = std::fs::read("tests/1.nbs").unwrap();
+    /// let (nbs, warnings) = Nbs::decode_lenient(&mut &data[..200], &DecodeLimits::default()).unwrap();
+    /// assert!(!warnings.is_empty());
+    /// assert!(nbs.noteblocks.layers.iter().any(|layer| !layer.notes.is_empty()));
+    /// ```
+    pub fn decode_lenient<R>(
+        reader: &mut R,
+        limits: &DecodeLimits,
+    ) -> Result<(Nbs, Vec<DecodeWarning>), NbsError>
+    where
+        R: ReadStringExt,
+    {
+        let mut reader = Decoder::lenient(reader, *limits);
+        let mut header = Header::decode_from(&mut reader)?;
+        let noteblocks = NoteBlocks::decode_from(&mut reader, &header)?;
+        let custom_instruments = CustomInstruments::decode_from(&mut reader, &header)?;
+        // Keep the layer count in sync with the layers that had to be added, or whose information is missing.
+        header.layer_count = i16::try_from(noteblocks.layers.len()).unwrap_or(i16::MAX);
+        Ok((
+            Nbs {
+                header,
+                noteblocks,
+                custom_instruments,
+            },
+            reader.warnings,
+        ))
+    }
+
     /// This method updates some parts of the Header to match the rest of the file
+    #[deprecated(note = "use `Nbs::fix`, which also corrects other issues")]
     pub fn update(&mut self) {
-        if self.format().version() >= 3 {
-            self.header.song_length = Some(self.noteblocks.calculate_length());
-        } else if self.format().version() == 0 {
-            self.header.old_song_length = self.noteblocks.calculate_length();
+        self.sync_header();
+    }
+
+    /// Checks the song for problems that break encoding, Note Block Studio or players.
+    pub fn validate(&self) -> Vec<Issue> {
+        validation::validate(self)
+    }
+
+    /// Corrects every fixable issue, like a stale song length or out of range values, and returns the remaining issues.
+    /// Fields the format requires are filled with their defaults and out of range values are clamped.
+    pub fn fix(&mut self) -> Vec<Issue> {
+        validation::fix(self)
+    }
+
+    /// Updates the song length, version number and layer count of the header to match the rest of the song.
+    pub(crate) fn sync_header(&mut self) {
+        if let Some(song_length) = validation::expected_song_length(self) {
+            if self.format().version() == 0 {
+                self.header.old_song_length = song_length;
+            } else {
+                self.header.song_length = Some(song_length);
+            }
         }
         if self.format().version() > 0 {
             self.header.version_number = Some(self.format().version());
         }
-        self.header.layer_count = self.noteblocks.layers.len() as i16;
+        self.sync_layer_count();
+    }
+
+    /// Saturates at `i16::MAX`, encoding fails with `NbsError::CountOutOfRange` for more layers.
+    fn sync_layer_count(&mut self) {
+        self.header.layer_count = i16::try_from(self.noteblocks.layers.len()).unwrap_or(i16::MAX);
+    }
+
+    /// Inserts a layer at `index`, moving all layers after it down.
+    /// Panics if `index` is greater than the amount of layers.
+    pub fn insert_layer(&mut self, index: usize, layer: Layer) {
+        self.noteblocks.insert_layer(index, layer);
+        self.sync_layer_count();
+    }
+
+    /// Removes the layer at `index` together with its notes, moving all layers after it up.
+    /// Panics if `index` is out of bounds.
+    pub fn remove_layer(&mut self, index: usize) -> Layer {
+        let layer = self.noteblocks.remove_layer(index);
+        self.sync_layer_count();
+        layer
+    }
+
+    /// Moves the layer at `from` to `to`, shifting the layers in between.
+    /// Panics if either index is out of bounds.
+    pub fn move_layer(&mut self, from: usize, to: usize) {
+        self.noteblocks.move_layer(from, to);
+    }
+
+    /// Merges several layers into as few layers as possible, see `NoteBlocks::merge_layers`.
+    ///
+    /// ```rust
+    /// use nbs::Nbs;
+    /// use std::fs::File;
+    ///
+    /// let mut nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+    /// let notes = nbs.noteblocks.events().count();
+    /// let all: Vec<usize> = (0..nbs.noteblocks.layers.len()).collect();
+    /// let layers = nbs.merge_layers(&all);
+    /// println!("The song fits into {} layers", layers.len());
+    /// assert_eq!(nbs.noteblocks.events().count(), notes);
+    /// assert_eq!(nbs.header.layer_count as usize, nbs.noteblocks.layers.len());
+    /// ```
+    pub fn merge_layers(&mut self, indices: &[usize]) -> Range<usize> {
+        let layers = self.noteblocks.merge_layers(indices);
+        self.sync_layer_count();
+        layers
+    }
+
+    /// Splits a layer into one layer per instrument, see `NoteBlocks::split_layer_by_instrument`.
+    pub fn split_layer_by_instrument(&mut self, index: usize) -> Range<usize> {
+        let layers = self.noteblocks.split_layer_by_instrument(index);
+        self.sync_layer_count();
+        layers
+    }
+
+    /// Removes the layers without notes at the end and returns how many were removed.
+    pub fn remove_empty_trailing_layers(&mut self) -> usize {
+        let count = self.noteblocks.remove_empty_trailing_layers();
+        self.sync_layer_count();
+        count
     }
 
     /// Enocde a NBS buffer,
@@ -153,18 +365,118 @@ impl Nbs {
         Ok(())
     }
 
+    /// Converts the song to another format, filling in defaults for fields the format introduces and dropping fields it does not support.
+    /// Custom instruments are moved to start after the vanilla instruments of the new format.
+    /// The returned report describes which information was lost.
+    pub fn convert_to(&mut self, format: NbsFormat) -> ConversionReport {
+        let mut report = ConversionReport::default();
+        let old_vannila_count = self.header.vannila_instrument_count().unwrap_or(16);
+        self.header.convert_to(format, &mut report);
+        let new_vannila_count = self.header.vannila_instrument_count().unwrap_or(16);
+        self.custom_instruments
+            .rebase(old_vannila_count, new_vannila_count);
+        for layer in &mut self.noteblocks.layers {
+            layer.convert_to(format, &mut report);
+            for note in layer.notes.values_mut() {
+                let instrument = note.instrument.rebase(old_vannila_count, new_vannila_count);
+                note.instrument = if format.is_new() {
+                    instrument
+                } else {
+                    instrument.closest_classic()
+                };
+                if note.instrument != instrument {
+                    report.replaced_instruments += 1;
+                }
+                note.convert_to(format, &mut report);
+            }
+        }
+        self.sync_header();
+        report
+    }
+
+    /// Transposes the notes in scope, see the `transpose` module.
+    /// Notes of the "Tempo Changer" instrument are left unchanged, since their pitch is the tempo.
+    pub fn transpose(&mut self, transpose: &Transpose) -> Result<TransposeReport, NbsError> {
+        let tempo_changer = self.custom_instruments.tempo_changer();
+        transpose::transpose(&mut self.noteblocks.layers, true, transpose, tempo_changer)
+    }
+
+    /// Re-grids the song to a different tempo while keeping its duration, see the `stretch` module.
+    /// The ticks of the notes and the loop start, the tempo and the tempo changers are stretched.
+    pub fn stretch(&mut self, stretch: &Stretch) -> Result<StretchReport, NbsError> {
+        stretch::stretch(self, stretch)
+    }
+
+    /// Copies the notes of a selection together with the custom instruments they use, see the `clipboard` module.
+    pub fn copy(&self, selection: &Selection) -> Fragment {
+        clipboard::copy(self, selection)
+    }
+
+    /// Removes the notes of a selection and returns them together with the custom instruments they use.
+    /// The notes after the selection are not moved.
+    pub fn cut(&mut self, selection: &Selection) -> Fragment {
+        clipboard::cut(self, selection)
+    }
+
+    /// Pastes a fragment with its first tick at `tick` and its first layer at `layer`.
+    /// Layers are added if the fragment does not fit, and its custom instruments are matched with or added to the custom instruments of the song.
+    /// The pasted notes are converted to the format of the song.
+    pub fn paste(
+        &mut self,
+        fragment: &Fragment,
+        tick: Tick,
+        layer: usize,
+        mode: PasteMode,
+    ) -> Result<PasteReport, NbsError> {
+        clipboard::paste(self, fragment, tick, layer, mode)
+    }
+
+    /// Plays another song after the end of this song, see the `combine` module.
+    /// The notes of the other song are pasted into the same layers, the song author and statistics of the header are merged.
+    pub fn append(&mut self, other: &Nbs, tempo: TempoMode) -> Result<CombineReport, NbsError> {
+        combine::append(self, other, tempo)
+    }
+
+    /// Plays another song from `offset` onwards, in new layers after the layers of this song.
+    /// The song author and statistics of the header are merged, like with `append`.
+    pub fn overlay(
+        &mut self,
+        other: &Nbs,
+        offset: Tick,
+        tempo: TempoMode,
+    ) -> Result<CombineReport, NbsError> {
+        combine::overlay(self, other, offset, tempo)
+    }
+
     /// Returns the NBS format for this
     pub fn format(&self) -> NbsFormat {
-        return self.header.format;
+        self.header.format
     }
 
     /// Returns the song ticks.
-    pub fn song_ticks(&self) -> i16 {
+    pub fn song_ticks(&self) -> Tick {
         self.noteblocks.calculate_length()
     }
 
-    /// Returns the song duration.
+    /// Returns the song duration, following the tempo changes of the song.
     pub fn song_length(&self) -> Duration {
-        Duration::from_secs_f32(self.song_ticks() as f32 / (self.header.song_tempo as f32 / 100.0))
+        self.tick_to_time(self.song_ticks())
+    }
+
+    /// Returns the tempo of the song over time, see the `tempo` module.
+    pub fn tempo_map(&self) -> TempoMap {
+        TempoMap::from_nbs(self)
+    }
+
+    /// Returns the time at which a tick is played, following the tempo changes of the song.
+    /// This creates the tempo map every time, use `tempo_map` for many conversions.
+    pub fn tick_to_time(&self, tick: Tick) -> Duration {
+        self.tempo_map().tick_to_time(tick)
+    }
+
+    /// Returns the tick that is played at a time, including the fraction of the tick that has passed.
+    /// This creates the tempo map every time, use `tempo_map` for many conversions.
+    pub fn time_to_tick(&self, time: Duration) -> f64 {
+        self.tempo_map().time_to_tick(time)
     }
 }
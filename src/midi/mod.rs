@@ -0,0 +1,869 @@
+//! Conversion between songs and Standard MIDI Files.
+//!
+//! A song is exported as a Type-1 file with one conductor track holding the tempo changes and time signature, followed by one track per layer.
+//! A NBS tick is treated as a sixteenth note, which is how Note Block Studio displays the time signature.
+//!
+//! Type-0 and Type-1 files can be imported, notes are quantized onto the NBS ticks of the chosen tempo.
+//!
+//! ## Example: Exporting a song
+//!
+//! ```rust
+//! use nbs::{
+//!     midi::{self, InstrumentMap, MidiInstrument},
+//!     noteblocks::instrument,
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let mut instruments = InstrumentMap::default();
+//! instruments.set(instrument::BIT, MidiInstrument::Program(81)); // Use "Lead 2 (sawtooth)" instead.
+//! let mut smf = Vec::new();
+//! midi::encode(&nbs, &instruments, &mut smf).unwrap();
+//! ```
+//! ## Example: Importing a MIDI file
+//!
+//! ```rust
+//! use nbs::{
+//!     midi::{self, ImportOptions},
+//!     Nbs, NbsFormat,
+//! };
+//! use std::fs::File;
+//!
+//! # let nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! # let mut smf = Vec::new();
+//! # midi::encode(&nbs, &Default::default(), &mut smf).unwrap();
+//! let mut options = ImportOptions::new(NbsFormat::OpenNoteBlockStudio(4));
+//! options.song_tempo = 2000; // Quantize onto 20 ticks per second.
+//! options.file_name = String::from("song.mid");
+//! let nbs = midi::decode(&mut &smf[..], &options).unwrap();
+//! assert_eq!(nbs.header.imported_file_name, "song.mid");
+//! ```
+
+use crate::{
+    header::Header,
+    noteblocks::{
+        instrument,
+        instrument::Instrument,
+        layer::Layer,
+        note::Note,
+        value::{Key, Panning, Velocity},
+        NoteBlocks,
+    },
+    Nbs, NbsError, NbsFormat, Tick,
+};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// The resolution of exported files in pulses per quarter note.
+pub const TICKS_PER_QUARTER: u16 = 96;
+/// The amount of NBS ticks that make up a quarter note.
+pub const NBS_TICKS_PER_QUARTER: u16 = 4;
+/// The MIDI note number of key 0 (A0).
+pub const KEY_OFFSET: u8 = 21;
+/// The MIDI channel reserved for percussion in General MIDI.
+pub const DRUM_CHANNEL: u8 = 9;
+/// The pitch bend range in cents assumed by General MIDI devices.
+pub const PITCH_BEND_RANGE: i32 = 200;
+/// The latest time in pulses whose delta time still fits into a variable-length quantity.
+const MAX_TIME: u32 = 0x0FFF_FFFF;
+
+/// How an instrument is played in a MIDI file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiInstrument {
+    /// A melodic General MIDI program (0-127). Notes keep their key.
+    Program(u8),
+    /// A percussion note on the drum channel (0-127). Notes always play this drum, regardless of their key.
+    Drum(u8),
+}
+
+/// A table of which MIDI instrument is used for each NBS instrument.
+/// The default table maps vanilla instruments to similar sounding General MIDI programs and drums.
+#[derive(Debug, Clone)]
+pub struct InstrumentMap {
+    instruments: HashMap<Instrument, MidiInstrument>,
+    /// Used for instruments that are not in the table, like custom instruments.
+    pub fallback: MidiInstrument,
+}
+
+impl InstrumentMap {
+    /// Creates an empty table, every instrument will use the fallback.
+    pub fn new(fallback: MidiInstrument) -> Self {
+        InstrumentMap {
+            instruments: HashMap::new(),
+            fallback,
+        }
+    }
+
+    /// Returns the MIDI instrument used for the given instrument.
+    pub fn get(&self, instrument: Instrument) -> MidiInstrument {
+        self.instruments
+            .get(&instrument)
+            .copied()
+            .unwrap_or(self.fallback)
+    }
+
+    /// Overrides the MIDI instrument used for the given instrument.
+    pub fn set(&mut self, instrument: Instrument, midi_instrument: MidiInstrument) {
+        self.instruments.insert(instrument, midi_instrument);
+    }
+
+    /// Returns the instrument that is mapped to the given MIDI instrument.
+    /// If multiple instruments are mapped to it, the one with the lowest id is returned.
+    pub fn find(&self, midi_instrument: MidiInstrument) -> Option<Instrument> {
+        self.instruments
+            .iter()
+            .filter(|(_, &mapped)| mapped == midi_instrument)
+            .map(|(&instrument, _)| instrument)
+            .min_by_key(|&instrument| u8::from(instrument))
+    }
+}
+
+impl Default for InstrumentMap {
+    fn default() -> Self {
+        let mut map = InstrumentMap::new(MidiInstrument::Program(0));
+        map.set(instrument::PIANO, MidiInstrument::Program(0)); // Acoustic Grand Piano
+        map.set(instrument::DOUBLE_BASS, MidiInstrument::Program(32)); // Acoustic Bass
+        map.set(instrument::BASS_DRUM, MidiInstrument::Drum(36)); // Bass Drum 1
+        map.set(instrument::SNARE_DRUM, MidiInstrument::Drum(38)); // Acoustic Snare
+        map.set(instrument::CLICK, MidiInstrument::Drum(42)); // Closed Hi-Hat
+        map.set(instrument::GUITAR, MidiInstrument::Program(24)); // Acoustic Guitar (nylon)
+        map.set(instrument::FLUTE, MidiInstrument::Program(73)); // Flute
+        map.set(instrument::BELL, MidiInstrument::Program(9)); // Glockenspiel
+        map.set(instrument::CHIME, MidiInstrument::Program(112)); // Tinkle Bell
+        map.set(instrument::XYLOPHONE, MidiInstrument::Program(13)); // Xylophone
+        map.set(instrument::IRON_XYLOPHONE, MidiInstrument::Program(11)); // Vibraphone
+        map.set(instrument::COW_BELL, MidiInstrument::Drum(56)); // Cowbell
+        map.set(instrument::DIDGERIDOO, MidiInstrument::Program(70)); // Bassoon
+        map.set(instrument::BIT, MidiInstrument::Program(80)); // Lead 1 (square)
+        map.set(instrument::BANJO, MidiInstrument::Program(105)); // Banjo
+        map.set(instrument::PLING, MidiInstrument::Program(4)); // Electric Piano 1
+        map
+    }
+}
+
+/// Encode a song as a Type-1 Standard MIDI File.
+///
+/// Every note lasts one NBS tick, since note blocks have no duration.
+/// Tempo changer notes become tempo changes of the conductor track.
+/// The velocity of a note is combined with the volume of its layer, and its panning with the stereo position of its layer.
+/// Fine pitch is rounded to the nearest key, the remainder is sent as pitch bend assuming a range of ±2 semitones.
+/// Every layer gets its own track, the 15 melodic channels are shared by the notes of all layers with the same program, panning and pitch bend.
+pub fn encode<W>(nbs: &Nbs, instruments: &InstrumentMap, writer: &mut W) -> Result<(), NbsError>
+where
+    W: WriteBytesExt,
+{
+    if nbs.header.song_tempo <= 0 {
+        return Err(NbsError::InvalidFormat);
+    }
+    let pulses_per_tick = (TICKS_PER_QUARTER / NBS_TICKS_PER_QUARTER) as u32;
+    // The note-off of a note at the last tick has to fit as well.
+    let pulses = |tick: Tick| {
+        u32::try_from(tick)
+            .ok()
+            .and_then(|tick| tick.checked_mul(pulses_per_tick))
+            .filter(|&time| time <= MAX_TIME - pulses_per_tick)
+            .ok_or(NbsError::InvalidData("tick too late for a MIDI file"))
+    };
+    let track_count = nbs.noteblocks.layers.len() + 1;
+    let track_count = u16::try_from(track_count).map_err(|_| NbsError::CountOutOfRange {
+        field: "track_count",
+        count: track_count,
+    })?;
+
+    writer.write_all(b"MThd")?;
+    writer.write_u32::<BigEndian>(6)?;
+    writer.write_u16::<BigEndian>(1)?;
+    writer.write_u16::<BigEndian>(track_count)?;
+    writer.write_u16::<BigEndian>(TICKS_PER_QUARTER)?;
+
+    let mut conductor = Track::new();
+    conductor.meta(0, 0x03, nbs.header.song_name.as_bytes());
+    for (tick, ticks_per_second) in nbs.tempo_map().changes() {
+        let micros_per_quarter = (NBS_TICKS_PER_QUARTER as f64 * 1_000_000.0 / ticks_per_second)
+            .round()
+            .min(0xFF_FFFF as f64) as u32;
+        conductor.meta(pulses(tick)?, 0x51, &micros_per_quarter.to_be_bytes()[1..]);
+    }
+    conductor.meta(0, 0x58, &[nbs.header.time_signature.max(1) as u8, 2, 24, 8]);
+    conductor.encode(writer)?;
+
+    let tempo_changer = nbs.custom_instruments.tempo_changer();
+    let mut tracks: Vec<Track> = nbs
+        .noteblocks
+        .layers
+        .iter()
+        .map(|layer| {
+            let mut track = Track::new();
+            track.meta(0, 0x03, layer.name.as_bytes());
+            track
+        })
+        .collect();
+    // The channels are shared by all tracks, so the notes of every layer are assigned to them in the order they are played.
+    let mut channels = Channels::new();
+    for (tick, layer_index, note) in nbs.noteblocks.events() {
+        // Notes before the first tick can not be played.
+        if tick < 0 || Some(note.instrument) == tempo_changer {
+            continue;
+        }
+        let layer = &nbs.noteblocks.layers[layer_index];
+        let track = &mut tracks[layer_index];
+        let time = pulses(tick)?;
+        let velocity =
+            note.velocity.unwrap_or_default().get() as i32 * layer.volume.get() as i32 / 100;
+        let velocity = ((velocity * 127 + 50) / 100).clamp(0, 127) as u8;
+        if velocity == 0 {
+            continue;
+        }
+        let panning = (note.panning.unwrap_or_default().get() as u32
+            + layer.stereo.unwrap_or_default().get() as u32)
+            / 2;
+        let pan = ((panning * 127 + 100) / 200).min(127) as u8;
+        let (channel, key) = match instruments.get(note.instrument) {
+            MidiInstrument::Drum(drum) => (DRUM_CHANNEL, drum.min(127)),
+            MidiInstrument::Program(program) => {
+                let cents = note.pitch.unwrap_or(0) as i32;
+                let semitones = (cents as f32 / 100.0).round() as i32;
+                let bend = (8192 + (cents - semitones * 100) * 8192 / PITCH_BEND_RANGE)
+                    .clamp(0, 16383) as u16;
+                let controls = Controls {
+                    program: program.min(127),
+                    pan,
+                    bend,
+                };
+                let (channel, previous) = channels.assign(tick, controls);
+                if previous.map(|p| p.program) != Some(controls.program) {
+                    track.event(time, 1, &[0xC0 | channel, controls.program]);
+                }
+                if previous.map(|p| p.bend) != Some(bend) {
+                    track.event(
+                        time,
+                        1,
+                        &[0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8],
+                    );
+                }
+                if previous.map(|p| p.pan) != Some(pan) {
+                    track.event(time, 1, &[0xB0 | channel, 10, pan]);
+                }
+                let key = note.key.get() as i32 + semitones + KEY_OFFSET as i32;
+                (channel, key.clamp(0, 127) as u8)
+            }
+        };
+        track.event(time, 2, &[0x90 | channel, key, velocity]);
+        track.event(time + pulses_per_tick, 0, &[0x80 | channel, key, 0]);
+    }
+    for track in tracks {
+        track.encode(writer)?;
+    }
+    Ok(())
+}
+
+/// Options for importing a MIDI file.
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// The format of the created song.
+    /// Velocity, panning and pitch bend are only kept for version 4 and above.
+    pub format: NbsFormat,
+    /// The tempo of the created song multiplied by 100, notes are quantized onto its ticks.
+    pub song_tempo: i16,
+    /// Stored as `imported_file_name` in the header.
+    pub file_name: String,
+    /// Instruments found in this table are preferred, others are guessed from their General MIDI program or drum.
+    pub instruments: InstrumentMap,
+}
+
+impl ImportOptions {
+    pub fn new(format: NbsFormat) -> Self {
+        ImportOptions {
+            format,
+            song_tempo: 1000,
+            file_name: String::new(),
+            instruments: InstrumentMap::default(),
+        }
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions::new(NbsFormat::OpenNoteBlockStudio(4))
+    }
+}
+
+/// Decode a Type-0 or Type-1 Standard MIDI File into a song.
+///
+/// Every source track (or channel, for Type-0 files) gets its own group of layers.
+/// Since a layer can only hold one note per tick, chords are split across as many layers as needed.
+/// Notes outside of the range A0-C8 are moved into it by octaves.
+pub fn decode<R>(reader: &mut R, options: &ImportOptions) -> Result<Nbs, NbsError>
+where
+    R: ReadBytesExt,
+{
+    if options.song_tempo <= 0 {
+        return Err(NbsError::InvalidFormat);
+    }
+    let (format, division, tracks) = read_chunks(reader)?;
+    if format > 1 {
+        return Err(NbsError::InvalidFormat);
+    }
+
+    // All tracks share the channels and the tempo, so the events are handled in the order they occur.
+    let mut events = Vec::new();
+    let mut track_names = HashMap::new();
+    for (track_index, track) in tracks.iter().enumerate() {
+        for (time, event) in parse_track(track)? {
+            if let Event::TrackName(name) = event {
+                track_names.entry(track_index).or_insert(name);
+            } else {
+                events.push((time, track_index, event));
+            }
+        }
+    }
+    events.sort_by_key(|(time, track_index, _)| (*time, *track_index));
+
+    let ticks_per_second = options.song_tempo as f64 / 100.0;
+    let mut time_signature = None;
+    let mut clock = Clock::new(division);
+    let mut channels = [ChannelState::new(); 16];
+    let mut notes: Vec<(usize, Tick, Note)> = Vec::new();
+    for (time, track_index, event) in events {
+        let seconds = clock.seconds(time);
+        match event {
+            Event::Tempo(micros_per_quarter) => clock.set_tempo(time, micros_per_quarter),
+            Event::TimeSignature(numerator) => {
+                time_signature.get_or_insert(numerator);
+            }
+            Event::Program { channel, program } => channels[channel as usize].program = program,
+            Event::Controller {
+                channel,
+                controller,
+                value,
+            } => channels[channel as usize].control(controller, value),
+            Event::PitchBend { channel, value } => channels[channel as usize].bend = value,
+            Event::TrackName(_) => {}
+            Event::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => {
+                let state = &channels[channel as usize];
+                let instrument = if channel == DRUM_CHANNEL {
+                    options
+                        .instruments
+                        .find(MidiInstrument::Drum(key))
+                        .unwrap_or_else(|| drum_instrument(key))
+                } else {
+                    options
+                        .instruments
+                        .find(MidiInstrument::Program(state.program))
+                        .unwrap_or_else(|| program_instrument(state.program))
+                };
+                let instrument = if options.format.is_new() {
+                    instrument
+                } else {
+                    instrument.closest_classic()
+                };
+                let cents = if channel == DRUM_CHANNEL {
+                    0
+                } else {
+                    (state.bend as i32 - 8192) * state.bend_range / 8192
+                };
+                let (key, pitch) = if channel == DRUM_CHANNEL {
+                    (45, 0)
+                } else if options.format.version() >= 4 {
+                    (key as i32 - KEY_OFFSET as i32, cents)
+                } else {
+                    let semitones = (cents as f32 / 100.0).round() as i32;
+                    (key as i32 - KEY_OFFSET as i32 + semitones, 0)
+                };
+                let mut key = key;
+                while key < 0 {
+                    key += 12;
+                }
+                while key > 87 {
+                    key -= 12;
+                }
+                let tick = (seconds * ticks_per_second).round() as Tick;
+                let group = if format == 0 {
+                    channel as usize
+                } else {
+                    track_index
+                };
+                let note = if options.format.version() >= 4 {
+                    Note::new(
+                        instrument,
+                        Key::saturating(key),
+                        Some(Velocity::saturating((velocity as i32 * 100 + 63) / 127)),
+                        Some(Panning::saturating(state.panning() as i32)),
+                        Some(pitch.clamp(-1200, 1200) as i16),
+                    )
+                } else {
+                    Note::new(instrument, Key::saturating(key), None, None, None)
+                };
+                notes.push((group, tick, note));
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<(Tick, Note)>> = BTreeMap::new();
+    for (group, tick, note) in notes {
+        groups.entry(group).or_default().push((tick, note));
+    }
+    let mut noteblocks = NoteBlocks::new();
+    for (group, notes) in groups {
+        let first_layer = noteblocks.layers.len();
+        let name = if format == 0 {
+            format!("Channel {}", group + 1)
+        } else {
+            track_names.get(&group).cloned().unwrap_or_default()
+        };
+        for (tick, note) in notes {
+            let free_layer = noteblocks.layers[first_layer..]
+                .iter()
+                .position(|layer| !layer.notes.contains_key(&tick));
+            let layer_index = match free_layer {
+                Some(index) => first_layer + index,
+                None => {
+                    let mut layer = Layer::from_format(options.format);
+                    layer.name = name.clone();
+                    noteblocks.layers.push(layer);
+                    noteblocks.layers.len() - 1
+                }
+            };
+            noteblocks.layers[layer_index].notes.insert(tick, note);
+        }
+    }
+
+    let mut header = Header::new(options.format);
+    header.song_tempo = options.song_tempo;
+    header.imported_file_name = options.file_name.clone();
+    if format == 1 {
+        if let Some(name) = track_names.get(&0) {
+            header.song_name = name.clone();
+        }
+    }
+    if let Some(numerator) = time_signature {
+        header.time_signature = numerator.clamp(2, 8) as i8;
+    }
+    let mut nbs = Nbs::from_componets(header, noteblocks, Default::default());
+    nbs.sync_header();
+    Ok(nbs)
+}
+
+/// Decode a MIDI file, storing its file name as `imported_file_name` in the header.
+pub fn import_file<P>(path: P, options: &ImportOptions) -> Result<Nbs, NbsError>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut options = options.clone();
+    if let Some(file_name) = path.file_name() {
+        options.file_name = file_name.to_string_lossy().into_owned();
+    }
+    decode(&mut File::open(path)?, &options)
+}
+
+/// Guesses the most similar vanilla instrument for a General MIDI program.
+fn program_instrument(program: u8) -> Instrument {
+    match program {
+        4 | 5 => instrument::PLING,
+        8 | 9 | 14 => instrument::BELL,
+        10 | 112 => instrument::CHIME,
+        11 | 114 => instrument::IRON_XYLOPHONE,
+        12 | 13 => instrument::XYLOPHONE,
+        15 | 104..=111 => instrument::BANJO,
+        0..=7 | 46 => instrument::PIANO,
+        24..=31 | 45 => instrument::GUITAR,
+        32..=39 => instrument::DOUBLE_BASS,
+        47 | 116..=118 => instrument::BASS_DRUM,
+        58 | 70 => instrument::DIDGERIDOO,
+        56..=63 | 80..=87 => instrument::BIT,
+        88..=103 => instrument::PLING,
+        113 => instrument::COW_BELL,
+        115 | 119..=127 => instrument::CLICK,
+        _ => instrument::FLUTE,
+    }
+}
+
+/// Guesses the most similar vanilla instrument for a General MIDI drum.
+fn drum_instrument(key: u8) -> Instrument {
+    match key {
+        35 | 36 | 41 | 43 | 45 | 47 | 48 | 50 => instrument::BASS_DRUM,
+        37..=40 | 49 | 51..=53 | 55 | 57 | 59 => instrument::SNARE_DRUM,
+        56 => instrument::COW_BELL,
+        _ => instrument::CLICK,
+    }
+}
+
+/// Reads the header and all track chunks, returning the format, the division and the raw tracks.
+fn read_chunks<R>(reader: &mut R) -> Result<(u16, u16, Vec<Vec<u8>>), NbsError>
+where
+    R: ReadBytesExt,
+{
+    let mut id = [0u8; 4];
+    reader.read_exact(&mut id)?;
+    if &id != b"MThd" {
+        return Err(NbsError::InvalidFormat);
+    }
+    let length = reader.read_u32::<BigEndian>()?;
+    if length < 6 {
+        return Err(NbsError::InvalidFormat);
+    }
+    let format = reader.read_u16::<BigEndian>()?;
+    let track_count = reader.read_u16::<BigEndian>()?;
+    let division = reader.read_u16::<BigEndian>()?;
+    io::copy(
+        &mut reader.by_ref().take(length as u64 - 6),
+        &mut io::sink(),
+    )?;
+    if division & 0x8000 != 0 {
+        // SMPTE timing needs a frame rate and a resolution within a frame.
+        if division >> 8 == 0x80 || division & 0xFF == 0 {
+            return Err(NbsError::InvalidFormat);
+        }
+    } else if division == 0 {
+        return Err(NbsError::InvalidFormat);
+    }
+    let mut tracks = Vec::with_capacity(track_count as usize);
+    while tracks.len() < track_count as usize {
+        reader.read_exact(&mut id)?;
+        let length = reader.read_u32::<BigEndian>()? as u64;
+        let mut chunk = Vec::new();
+        reader.by_ref().take(length).read_to_end(&mut chunk)?;
+        if chunk.len() as u64 != length {
+            return Err(NbsError::InvalidFormat);
+        }
+        // Unknown chunks have to be skipped.
+        if &id == b"MTrk" {
+            tracks.push(chunk);
+        }
+    }
+    Ok((format, division, tracks))
+}
+
+/// The events of a MIDI file that matter for the import.
+enum Event {
+    NoteOn {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    Controller {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    Program {
+        channel: u8,
+        program: u8,
+    },
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+    Tempo(u32),
+    TimeSignature(u8),
+    TrackName(String),
+}
+
+/// Parses the events of a track chunk, returning them with their absolute time in pulses.
+fn parse_track(mut data: &[u8]) -> Result<Vec<(u64, Event)>, NbsError> {
+    let mut events = Vec::new();
+    let mut time = 0u64;
+    let mut running_status = None;
+    while !data.is_empty() {
+        time += read_var_len(&mut data)? as u64;
+        let status = match data.first() {
+            Some(&byte) if byte >= 0x80 => {
+                data = &data[1..];
+                byte
+            }
+            Some(_) => running_status.ok_or(NbsError::InvalidFormat)?,
+            None => return Err(NbsError::InvalidFormat),
+        };
+        match status {
+            0xFF => {
+                let kind = data.read_u8()?;
+                let length = read_var_len(&mut data)? as usize;
+                if data.len() < length {
+                    return Err(NbsError::InvalidFormat);
+                }
+                let (payload, rest) = data.split_at(length);
+                data = rest;
+                match (kind, payload.len()) {
+                    (0x2F, _) => break,
+                    (0x03, _) => events.push((
+                        time,
+                        Event::TrackName(String::from_utf8_lossy(payload).into_owned()),
+                    )),
+                    (0x51, 3) => events.push((
+                        time,
+                        Event::Tempo(u32::from_be_bytes([0, payload[0], payload[1], payload[2]])),
+                    )),
+                    (0x58, 4) => events.push((time, Event::TimeSignature(payload[0]))),
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                let length = read_var_len(&mut data)? as usize;
+                if data.len() < length {
+                    return Err(NbsError::InvalidFormat);
+                }
+                data = &data[length..];
+                running_status = None;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                let first = data.read_u8()? & 0x7F;
+                let second = match status & 0xF0 {
+                    0xC0 | 0xD0 => 0,
+                    _ => data.read_u8()? & 0x7F,
+                };
+                match status & 0xF0 {
+                    0x90 if second > 0 => events.push((
+                        time,
+                        Event::NoteOn {
+                            channel,
+                            key: first,
+                            velocity: second,
+                        },
+                    )),
+                    0xB0 => events.push((
+                        time,
+                        Event::Controller {
+                            channel,
+                            controller: first,
+                            value: second,
+                        },
+                    )),
+                    0xC0 => events.push((
+                        time,
+                        Event::Program {
+                            channel,
+                            program: first,
+                        },
+                    )),
+                    0xE0 => events.push((
+                        time,
+                        Event::PitchBend {
+                            channel,
+                            value: first as u16 | (second as u16) << 7,
+                        },
+                    )),
+                    _ => {}
+                }
+            }
+            _ => return Err(NbsError::InvalidFormat),
+        }
+    }
+    Ok(events)
+}
+
+/// Reads a variable-length quantity.
+fn read_var_len(data: &mut &[u8]) -> Result<u32, NbsError> {
+    let mut value = 0u32;
+    for _ in 0..4 {
+        let byte = data.read_u8()?;
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(NbsError::InvalidFormat)
+}
+
+/// Converts pulses into seconds, following the tempo changes of the file.
+struct Clock {
+    division: u16,
+    /// The pulse and the elapsed seconds of the last tempo change.
+    last_change: (u64, f64),
+    micros_per_quarter: u32,
+}
+
+impl Clock {
+    fn new(division: u16) -> Self {
+        Clock {
+            division,
+            last_change: (0, 0.0),
+            micros_per_quarter: 500_000,
+        }
+    }
+
+    fn seconds(&self, time: u64) -> f64 {
+        let pulses = (time - self.last_change.0) as f64;
+        let seconds_per_pulse = if self.division & 0x8000 != 0 {
+            // SMPTE timing, the upper byte is the negative frames per second.
+            let frames_per_second = -((self.division >> 8) as u8 as i8 as i16) as f64;
+            1.0 / (frames_per_second * (self.division & 0xFF) as f64)
+        } else {
+            self.micros_per_quarter as f64 / 1_000_000.0 / self.division as f64
+        };
+        self.last_change.1 + pulses * seconds_per_pulse
+    }
+
+    fn set_tempo(&mut self, time: u64, micros_per_quarter: u32) {
+        self.last_change = (time, self.seconds(time));
+        self.micros_per_quarter = micros_per_quarter.max(1);
+    }
+}
+
+/// The controller state of a MIDI channel.
+#[derive(Clone, Copy)]
+struct ChannelState {
+    program: u8,
+    pan: u8,
+    bend: u16,
+    /// The pitch bend range in cents.
+    bend_range: i32,
+    /// The selected registered parameter, as (MSB, LSB).
+    parameter: (u8, u8),
+}
+
+impl ChannelState {
+    fn new() -> Self {
+        ChannelState {
+            program: 0,
+            pan: 64,
+            bend: 8192,
+            bend_range: PITCH_BEND_RANGE,
+            parameter: (127, 127),
+        }
+    }
+
+    /// Returns the pan position as NBS panning, mapping 64 to center (100).
+    fn panning(&self) -> u8 {
+        if self.pan <= 64 {
+            (self.pan as u32 * 100 / 64) as u8
+        } else {
+            (100 + (self.pan as u32 - 64) * 100 / 63) as u8
+        }
+    }
+
+    fn control(&mut self, controller: u8, value: u8) {
+        match controller {
+            10 => self.pan = value,
+            101 => self.parameter.0 = value,
+            100 => self.parameter.1 = value,
+            // Data entry for the pitch bend sensitivity, in semitones and cents.
+            6 if self.parameter == (0, 0) => {
+                self.bend_range = value as i32 * 100 + self.bend_range % 100
+            }
+            38 if self.parameter == (0, 0) => {
+                self.bend_range = self.bend_range / 100 * 100 + value as i32
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes a variable-length quantity, as used for delta times and lengths.
+pub(crate) fn write_var_len(buffer: &mut Vec<u8>, value: u32) {
+    let mut bytes = [0u8; 5];
+    let mut index = bytes.len() - 1;
+    let mut value = value;
+    bytes[index] = (value & 0x7F) as u8;
+    value >>= 7;
+    while value > 0 {
+        index -= 1;
+        bytes[index] = (value & 0x7F) as u8 | 0x80;
+        value >>= 7;
+    }
+    buffer.extend_from_slice(&bytes[index..]);
+}
+
+/// The program, pan and pitch bend a melodic channel is set to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Controls {
+    program: u8,
+    pan: u8,
+    bend: u16,
+}
+
+/// Assigns the melodic channels to notes, since a channel can only play with one set of controls at a time.
+struct Channels {
+    /// Every channel with its controls and the last tick it played a note.
+    channels: Vec<(u8, Option<Controls>, Tick)>,
+}
+
+impl Channels {
+    fn new() -> Self {
+        Channels {
+            // Channel 9 is reserved for drums.
+            channels: (0..16)
+                .filter(|&channel| channel != DRUM_CHANNEL)
+                .map(|channel| (channel, None, Tick::MIN))
+                .collect(),
+        }
+    }
+
+    /// Returns the channel for a note and the controls it was set to before, the ticks have to be in order.
+    /// A channel that already has the controls is preferred, otherwise the channel that has been silent the longest.
+    /// Only when more than 15 different controls are played at the same tick, a note changes the controls of another one.
+    fn assign(&mut self, tick: Tick, controls: Controls) -> (u8, Option<Controls>) {
+        let index = match self
+            .channels
+            .iter()
+            .position(|(_, current, _)| *current == Some(controls))
+        {
+            Some(index) => index,
+            None => (0..self.channels.len())
+                .min_by_key(|&index| self.channels[index].2)
+                .unwrap(),
+        };
+        let (channel, current, last_tick) = &mut self.channels[index];
+        let previous = current.replace(controls);
+        *last_tick = tick;
+        (*channel, previous)
+    }
+}
+
+/// The events of a single track, ordered before being written.
+struct Track {
+    /// Absolute time, priority and the raw event. Events at the same time are sorted by priority.
+    events: Vec<(u32, u8, Vec<u8>)>,
+}
+
+impl Track {
+    fn new() -> Self {
+        Track { events: Vec::new() }
+    }
+
+    fn event(&mut self, time: u32, priority: u8, data: &[u8]) {
+        self.events.push((time, priority, data.to_vec()));
+    }
+
+    fn meta(&mut self, time: u32, kind: u8, data: &[u8]) {
+        let mut event = vec![0xFF, kind];
+        write_var_len(&mut event, data.len() as u32);
+        event.extend_from_slice(data);
+        self.events.push((time, 0, event));
+    }
+
+    fn encode<W>(mut self, writer: &mut W) -> Result<(), NbsError>
+    where
+        W: WriteBytesExt,
+    {
+        // A stable sort keeps the insertion order of events with the same time and priority.
+        self.events
+            .sort_by_key(|(time, priority, _)| (*time, *priority));
+        let mut data = Vec::new();
+        let mut last_time = 0;
+        for (time, _, event) in &self.events {
+            write_var_len(&mut data, time - last_time);
+            data.extend_from_slice(event);
+            last_time = *time;
+        }
+        write_var_len(&mut data, 0);
+        data.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+        writer.write_all(b"MTrk")?;
+        writer.write_u32::<BigEndian>(data.len() as u32)?;
+        writer.write_all(&data)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Iterators over the notes of all layers in the order they are played.
+//!
+//! ## Example: Printing every tick of a song
+//!
+//! ```rust
+//! use nbs::Nbs;
+//! use std::fs::File;
+//!
+//! let nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! for (tick, notes) in nbs.noteblocks.columns() {
+//!     let keys: Vec<u8> = notes.iter().map(|(_, note)| note.key.get()).collect();
+//!     println!("{}: {:?}", tick, keys);
+//! }
+//! ```
+
+use super::{note::Note, Layer};
+use crate::Tick;
+use std::{
+    cmp::Reverse,
+    collections::{btree_map, BinaryHeap},
+    iter::Peekable,
+};
+
+/// Merges the notes of all layers into tick-then-layer order.
+struct Merge<I: Iterator> {
+    layers: Vec<Peekable<I>>,
+    /// The next tick of every layer that has notes left, smallest first.
+    next: BinaryHeap<Reverse<(Tick, usize)>>,
+}
+
+impl<'a, I, N> Merge<I>
+where
+    I: Iterator<Item = (&'a Tick, N)>,
+{
+    fn new(layers: impl Iterator<Item = I>) -> Self {
+        let mut layers: Vec<_> = layers.map(Iterator::peekable).collect();
+        let mut next = BinaryHeap::with_capacity(layers.len());
+        for (layer_index, layer) in layers.iter_mut().enumerate() {
+            if let Some((&tick, _)) = layer.peek() {
+                next.push(Reverse((tick, layer_index)));
+            }
+        }
+        Merge { layers, next }
+    }
+}
+
+impl<'a, I, N> Iterator for Merge<I>
+where
+    I: Iterator<Item = (&'a Tick, N)>,
+{
+    type Item = (Tick, usize, N);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((tick, layer_index)) = self.next.pop()?;
+        let layer = &mut self.layers[layer_index];
+        let (_, note) = layer.next()?;
+        if let Some((&next_tick, _)) = layer.peek() {
+            self.next.push(Reverse((next_tick, layer_index)));
+        }
+        Some((tick, layer_index, note))
+    }
+}
+
+/// Yields `(tick, layer_index, &Note)` for every note, ordered by tick and then by layer.
+/// Created by `NoteBlocks::events`.
+pub struct Events<'a>(Merge<btree_map::Iter<'a, Tick, Note>>);
+
+impl<'a> Events<'a> {
+    pub(crate) fn new(layers: &'a [Layer]) -> Self {
+        Events(Merge::new(layers.iter().map(|layer| layer.notes.iter())))
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = (Tick, usize, &'a Note);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+/// Yields `(tick, layer_index, &mut Note)` for every note, ordered by tick and then by layer.
+/// Created by `NoteBlocks::events_mut`.
+pub struct EventsMut<'a>(Merge<btree_map::IterMut<'a, Tick, Note>>);
+
+impl<'a> EventsMut<'a> {
+    pub(crate) fn new(layers: &'a mut [Layer]) -> Self {
+        EventsMut(Merge::new(
+            layers.iter_mut().map(|layer| layer.notes.iter_mut()),
+        ))
+    }
+}
+
+impl<'a> Iterator for EventsMut<'a> {
+    type Item = (Tick, usize, &'a mut Note);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+/// Groups events by tick, yielding every tick that has notes together with `(layer_index, note)` in layer order.
+/// Created by `NoteBlocks::columns` and `NoteBlocks::columns_mut`.
+pub struct Columns<I: Iterator> {
+    events: Peekable<I>,
+}
+
+impl<I: Iterator> Columns<I> {
+    pub(crate) fn new(events: I) -> Self {
+        Columns {
+            events: events.peekable(),
+        }
+    }
+}
+
+impl<I, N> Iterator for Columns<I>
+where
+    I: Iterator<Item = (Tick, usize, N)>,
+{
+    type Item = (Tick, Vec<(usize, N)>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (tick, layer_index, note) = self.events.next()?;
+        let mut notes = vec![(layer_index, note)];
+        while let Some((_, layer_index, note)) = self.events.next_if(|(t, _, _)| *t == tick) {
+            notes.push((layer_index, note));
+        }
+        Some((tick, notes))
+    }
+}
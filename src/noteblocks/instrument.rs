@@ -1,4 +1,12 @@
-use crate::{header::Header, NbsError};
+use super::value::Key;
+use crate::{
+    error::{Limit, Repair, Section},
+    header::Header,
+    io::{DecodeLimits, Decoder},
+    NbsError,
+};
+use byteorder::ReadBytesExt;
+use std::convert::TryFrom;
 
 pub const PIANO: Instrument = Instrument::Vanilla(0);
 pub const DOUBLE_BASS: Instrument = Instrument::Vanilla(1);
@@ -17,33 +25,64 @@ pub const BIT: Instrument = Instrument::Vanilla(13);
 pub const BANJO: Instrument = Instrument::Vanilla(14);
 pub const PLING: Instrument = Instrument::Vanilla(15);
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instrument {
-    Vanilla(i8),
-    Custom(i8),
+    Vanilla(u8),
+    Custom(u8),
 }
 
 impl Instrument {
     pub fn is_custom(&self) -> bool {
+        matches!(self, Instrument::Custom(_))
+    }
+
+    /// Moves the id of a custom instrument, for when the amount of vanilla instruments changes.
+    /// Vanilla instruments are returned unchanged.
+    pub(crate) fn rebase(self, old_vannila_count: u8, new_vannila_count: u8) -> Instrument {
         match self {
-            Instrument::Custom(_) => true,
-            _ => false,
+            Instrument::Custom(id) => Instrument::Custom(
+                id.saturating_sub(old_vannila_count)
+                    .saturating_add(new_vannila_count),
+            ),
+            instrument => instrument,
+        }
+    }
+
+    /// Returns the most similar instrument that is available in the classic NoteBlockStudio format, which only has the first 10 vanilla instruments.
+    /// Custom instruments are returned unchanged.
+    pub fn closest_classic(&self) -> Instrument {
+        match *self {
+            IRON_XYLOPHONE => XYLOPHONE,
+            COW_BELL => BELL,
+            DIDGERIDOO => DOUBLE_BASS,
+            BIT | PLING => PIANO,
+            BANJO => GUITAR,
+            instrument => instrument,
         }
     }
 }
 
-impl Into<i8> for Instrument {
-    fn into(self) -> i8 {
-        match self {
+impl From<Instrument> for u8 {
+    fn from(instrument: Instrument) -> u8 {
+        match instrument {
             Instrument::Custom(id) | Instrument::Vanilla(id) => id,
         }
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 pub struct CustomInstruments {
     instruments: Vec<CustomInstrumentInfo>,
 }
 
+impl Default for CustomInstruments {
+    fn default() -> Self {
+        CustomInstruments::new()
+    }
+}
+
 impl CustomInstruments {
     pub fn new() -> Self {
         CustomInstruments {
@@ -55,18 +94,49 @@ impl CustomInstruments {
     where
         R: crate::ReadStringExt,
     {
-        let instrument_count = reader.read_i8()?;
-        let mut custom_instruments = CustomInstruments {
-            instruments: Vec::with_capacity(instrument_count as usize),
-        };
+        CustomInstruments::decode_from(&mut Decoder::new(reader, DecodeLimits::default()), header)
+    }
+
+    pub(crate) fn decode_from<R>(
+        reader: &mut Decoder<R>,
+        header: &Header,
+    ) -> Result<CustomInstruments, NbsError>
+    where
+        R: std::io::Read,
+    {
+        reader.enter(Section::CustomInstruments);
+        let mut custom_instruments = CustomInstruments::new();
+        let result = custom_instruments.decode_instruments(reader, header);
+        reader.recover(result, Repair::MissingCustomInstruments)?;
+        Ok(custom_instruments)
+    }
+
+    fn decode_instruments<R>(
+        &mut self,
+        reader: &mut Decoder<R>,
+        header: &Header,
+    ) -> Result<(), NbsError>
+    where
+        R: std::io::Read,
+    {
+        let instrument_count = reader.field("instrument_count", |r| {
+            let instrument_count = r.read_u8()?;
+            r.check_limit(Limit::CustomInstruments, instrument_count as usize)?;
+            Ok(instrument_count)
+        })?;
+        self.instruments.reserve(instrument_count as usize);
         for id in 0..instrument_count {
+            reader.instrument = Some(id);
             // We don't want to overlap with vannila instruments.
-            let instrument = Instrument::Custom(id + header.vannila_instrument_count()?);
-            let name = reader.read_string()?;
-            let file_name = reader.read_string()?;
-            let pitch = reader.read_i8()?;
-            let press_key = reader.read_i8()? == 1;
-            custom_instruments.instruments.push(CustomInstrumentInfo {
+            let instrument = Instrument::Custom(reader.field("instrument", |_| {
+                id.checked_add(header.vannila_instrument_count()?)
+                    .ok_or(NbsError::InvalidData("too many custom instruments"))
+            })?);
+            let name = reader.field_string("name")?;
+            let file_name = reader.field_string("file_name")?;
+            let pitch = Key::from_raw(reader.field_u8("pitch")?);
+            let press_key = reader.field_i8("press_key")? == 1;
+            self.instruments.push(CustomInstrumentInfo {
                 instrument,
                 name,
                 file_name,
@@ -74,28 +144,101 @@ impl CustomInstruments {
                 press_key,
             })
         }
-        Ok(custom_instruments)
+        Ok(())
     }
 
     pub fn encode<W>(&self, writer: &mut W) -> Result<(), NbsError>
     where
         W: crate::WriteStringExt,
     {
-        writer.write_i8(self.instruments.len() as i8)?;
+        let count =
+            u8::try_from(self.instruments.len()).map_err(|_| NbsError::CountOutOfRange {
+                field: "instrument_count",
+                count: self.instruments.len(),
+            })?;
+        writer.write_u8(count)?;
         for instrument in &self.instruments {
             writer.write_string(&instrument.name)?;
             writer.write_string(&instrument.file_name)?;
-            writer.write_i8(instrument.pitch)?;
+            writer.write_u8(instrument.pitch.into())?;
             writer.write_i8(if instrument.press_key { 1 } else { 0 })?;
         }
         Ok(())
     }
+
+    /// Moves the ids of all custom instruments, for when the amount of vanilla instruments changes.
+    pub(crate) fn rebase(&mut self, old_vannila_count: u8, new_vannila_count: u8) {
+        for info in &mut self.instruments {
+            info.instrument = info.instrument.rebase(old_vannila_count, new_vannila_count);
+        }
+    }
+
+    /// Gives the custom instruments new ids, in order.
+    pub(crate) fn renumber(&mut self, ids: &[Instrument]) {
+        for (info, &id) in self.instruments.iter_mut().zip(ids) {
+            info.instrument = id;
+        }
+    }
+
+    /// Returns the information about a custom instrument, if it exists.
+    pub fn get(&self, instrument: Instrument) -> Option<&CustomInstrumentInfo> {
+        self.instruments
+            .iter()
+            .find(|info| info.instrument == instrument)
+    }
+
+    /// Adds a custom instrument after the existing ones and returns its id, replacing the id in `info`.
+    /// `vannila_instrument_count` is the amount of vanilla instruments of the song, see `Header::vannila_instrument_count`.
+    /// Fails if the id of the instrument would not fit into a byte.
+    pub fn add(
+        &mut self,
+        vannila_instrument_count: u8,
+        mut info: CustomInstrumentInfo,
+    ) -> Result<Instrument, NbsError> {
+        let id = vannila_instrument_count as usize + self.instruments.len();
+        let id = u8::try_from(id).map_err(|_| NbsError::CountOutOfRange {
+            field: "instrument_count",
+            count: self.instruments.len() + 1,
+        })?;
+        let instrument = Instrument::Custom(id);
+        info.instrument = instrument;
+        self.instruments.push(info);
+        Ok(instrument)
+    }
+
+    /// Returns the "Tempo Changer" instrument of Open Note Block Studio, if the song has one.
+    pub fn tempo_changer(&self) -> Option<Instrument> {
+        self.instruments
+            .iter()
+            .find(|info| info.is_tempo_changer())
+            .map(|info| info.instrument)
+    }
+
+    /// Returns an iterator over all custom instruments.
+    pub fn iter(&self) -> std::slice::Iter<'_, CustomInstrumentInfo> {
+        self.instruments.iter()
+    }
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CustomInstrumentInfo {
     pub instrument: Instrument,
     pub name: String,
     pub file_name: String,
-    pub pitch: i8,
+    /// The key the sound file plays at without any pitch change, from 0-87.
+    /// Default is 45 (F#4), just like vanilla note blocks.
+    pub pitch: Key,
     pub press_key: bool,
 }
+
+impl CustomInstrumentInfo {
+    /// The name of the custom instrument Open Note Block Studio uses to change the tempo mid-song.
+    pub const TEMPO_CHANGER: &'static str = "Tempo Changer";
+
+    /// Returns true if notes of this instrument change the tempo instead of making a sound.
+    /// The fine pitch of such a note is the new tempo in beats per minute, where one beat lasts 4 ticks.
+    pub fn is_tempo_changer(&self) -> bool {
+        self.name == CustomInstrumentInfo::TEMPO_CHANGER
+    }
+}
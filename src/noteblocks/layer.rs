@@ -1,19 +1,38 @@
-use super::note::Note;
-use crate::NbsFormat;
-use std::collections::HashMap;
+use super::{
+    note::Note,
+    value::{Panning, Volume},
+};
+use crate::{
+    conversion::ConversionReport,
+    transpose::{self, Transpose, TransposeReport},
+    NbsError, NbsFormat, Tick,
+};
+use std::collections::BTreeMap;
 
 /// A Layer contains an list of notes and some additional information.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Layer {
     /// Name of the layer.
     pub name: String,
-    /// Only avabile in the new format version 4.
+    /// Only avabile in the new format since version 4.
     pub locked: Option<bool>,
+    /// Whether only this layer is played.
+    /// Only avabile in the new format since version 5.
+    pub solo: Option<bool>,
     /// Layer volume.
-    pub volume: i8,
+    pub volume: Volume,
     /// Only avabile in the new format since version 2.
-    pub stereo: Option<i8>,
-    pub notes: HashMap<i16, Note>,
+    pub stereo: Option<Panning>,
+    /// The notes of the layer by tick, iterated in order of their tick.
+    /// Use `notes.range(start..end)` to get the notes in a part of the song.
+    pub notes: BTreeMap<Tick, Note>,
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Layer::new()
+    }
 }
 
 impl Layer {
@@ -22,9 +41,10 @@ impl Layer {
         Layer {
             name: String::new(),
             locked: None,
-            volume: 100,
+            solo: None,
+            volume: Volume::default(),
             stereo: None,
-            notes: HashMap::new(),
+            notes: BTreeMap::new(),
         }
     }
 
@@ -35,10 +55,50 @@ impl Layer {
         if format.version() >= 4 {
             layer.locked = Some(false);
         }
-        layer.volume = 100;
+        if format.version() >= 5 {
+            layer.solo = Some(false);
+        }
         if format.version() >= 2 {
-            layer.stereo = Some(100);
+            layer.stereo = Some(Panning::CENTER);
         }
         layer
     }
+
+    /// Creates an empty layer with the same name and settings.
+    pub(crate) fn with_settings(&self) -> Layer {
+        Layer {
+            name: self.name.clone(),
+            locked: self.locked,
+            solo: self.solo,
+            volume: self.volume,
+            stereo: self.stereo,
+            notes: BTreeMap::new(),
+        }
+    }
+
+    /// Fills or drops the fields that depend on the format, recording discarded values in the report.
+    /// The notes of the layer are not converted.
+    pub fn convert_to(&mut self, format: NbsFormat, report: &mut ConversionReport) {
+        if format.version() >= 4 {
+            self.locked.get_or_insert(false);
+        } else if self.locked.take() == Some(true) {
+            report.layer_locks += 1;
+        }
+        if format.version() >= 5 {
+            self.solo.get_or_insert(false);
+        } else if self.solo.take() == Some(true) {
+            report.layer_solos += 1;
+        }
+        if format.version() >= 2 {
+            self.stereo.get_or_insert(Panning::CENTER);
+        } else if self.stereo.take().unwrap_or_default() != Panning::CENTER {
+            report.layer_stereo += 1;
+        }
+    }
+
+    /// Transposes the notes in scope, see the `transpose` module.
+    /// The layer scope of `transpose` is ignored, and like with `NoteBlocks::transpose` "Tempo Changer" notes are transposed too.
+    pub fn transpose(&mut self, transpose: &Transpose) -> Result<TransposeReport, NbsError> {
+        transpose::transpose(std::slice::from_mut(self), false, transpose, None)
+    }
 }
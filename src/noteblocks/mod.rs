@@ -1,14 +1,26 @@
-use crate::{header::Header, NbsError, NbsFormat};
-use byteorder::LittleEndian;
+use crate::{
+    error::{Limit, Repair, Section},
+    header::Header,
+    io::{DecodeLimits, Decoder},
+    transpose::{self, Transpose, TransposeReport},
+    NbsError, NbsFormat, Tick,
+};
+use byteorder::{LittleEndian, ReadBytesExt};
+use events::{Columns, Events, EventsMut};
 use instrument::Instrument;
 use layer::Layer;
 use note::Note;
+use std::{collections::BTreeMap, convert::TryFrom, ops::Range};
+use value::{Key, Panning, Velocity, Volume};
 
+pub mod events;
 pub mod instrument;
 pub mod layer;
 pub mod note;
+pub mod value;
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NoteBlocks {
     /// Layers of the File.
     pub layers: Vec<Layer>,
@@ -20,148 +32,388 @@ impl NoteBlocks {
     }
 }
 
+impl Default for NoteBlocks {
+    fn default() -> Self {
+        NoteBlocks::new()
+    }
+}
+
 impl NoteBlocks {
-    pub fn calculate_length(&self) -> i16 {
-        let mut length: i16 = 0;
-        for layer in &self.layers {
-            if layer.notes.len() > 0 {
-                let last_note = layer.notes.iter().max_by(|x, y| x.0.cmp(&y.0)).unwrap();
-                if last_note.0 > &length {
-                    length = *last_note.0;
+    pub fn calculate_length(&self) -> Tick {
+        self.layers
+            .iter()
+            .filter_map(|layer| layer.notes.keys().next_back())
+            .max()
+            .copied()
+            .unwrap_or(0)
+            .max(0)
+    }
+
+    /// Returns every note as `(tick, layer_index, &Note)`, ordered by tick and then by layer.
+    pub fn events(&self) -> Events<'_> {
+        Events::new(&self.layers)
+    }
+
+    /// Returns every note as `(tick, layer_index, &mut Note)`, ordered by tick and then by layer.
+    pub fn events_mut(&mut self) -> EventsMut<'_> {
+        EventsMut::new(&mut self.layers)
+    }
+
+    /// Returns every tick that has notes, together with its notes in layer order.
+    pub fn columns(&self) -> Columns<Events<'_>> {
+        Columns::new(self.events())
+    }
+
+    /// Returns every tick that has notes, together with mutable references to its notes in layer order.
+    pub fn columns_mut(&mut self) -> Columns<EventsMut<'_>> {
+        Columns::new(self.events_mut())
+    }
+
+    /// Transposes the notes in scope, see the `transpose` module.
+    /// The custom instruments are unknown here, so "Tempo Changer" notes are transposed like any other note,
+    /// which changes their tempo. Use `Nbs::transpose` to leave them unchanged.
+    pub fn transpose(&mut self, transpose: &Transpose) -> Result<TransposeReport, NbsError> {
+        transpose::transpose(&mut self.layers, true, transpose, None)
+    }
+
+    /// Inserts a layer at `index`, moving all layers after it down.
+    /// Panics if `index` is greater than the amount of layers.
+    pub fn insert_layer(&mut self, index: usize, layer: Layer) {
+        self.layers.insert(index, layer);
+    }
+
+    /// Removes the layer at `index` together with its notes, moving all layers after it up.
+    /// Panics if `index` is out of bounds.
+    pub fn remove_layer(&mut self, index: usize) -> Layer {
+        self.layers.remove(index)
+    }
+
+    /// Moves the layer at `from` to `to`, shifting the layers in between.
+    /// Panics if either index is out of bounds.
+    pub fn move_layer(&mut self, from: usize, to: usize) {
+        assert!(to < self.layers.len(), "layer index out of bounds");
+        let layer = self.layers.remove(from);
+        self.layers.insert(to, layer);
+    }
+
+    /// Merges the notes of several layers into as few layers as possible, starting at the first of them.
+    /// Notes that collide with a note on the same tick spill into new layers, which are inserted after the first layer.
+    /// All merged layers take the settings of the first layer, like its volume, and are returned as a range of indices.
+    /// Panics if an index is out of bounds.
+    pub fn merge_layers(&mut self, indices: &[usize]) -> Range<usize> {
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+        let first = match indices.first() {
+            Some(&first) => first,
+            None => return 0..0,
+        };
+        assert!(
+            indices.iter().all(|&index| index < self.layers.len()),
+            "layer index out of bounds"
+        );
+        let mut notes = Vec::new();
+        for &index in indices[1..].iter().rev() {
+            notes.push(self.layers.remove(index).notes);
+        }
+        let mut merged = vec![std::mem::take(&mut self.layers[first].notes)];
+        // Lower layers come first, so they keep their place when notes collide.
+        for layer_notes in notes.into_iter().rev() {
+            for (tick, note) in layer_notes {
+                match merged.iter_mut().find(|notes| !notes.contains_key(&tick)) {
+                    Some(notes) => {
+                        notes.insert(tick, note);
+                    }
+                    None => {
+                        let mut notes = BTreeMap::new();
+                        notes.insert(tick, note);
+                        merged.push(notes);
+                    }
                 }
             }
         }
-        length
+        let count = merged.len();
+        let mut merged = merged.into_iter();
+        self.layers[first].notes = merged.next().unwrap_or_default();
+        for (offset, notes) in merged.enumerate() {
+            let mut layer = self.layers[first].with_settings();
+            layer.notes = notes;
+            self.layers.insert(first + 1 + offset, layer);
+        }
+        first..first + count
+    }
+
+    /// Splits a layer into one layer per instrument, ordered by instrument.
+    /// The new layers take the settings of the split layer and are inserted after it, they are returned as a range of indices.
+    /// Panics if `index` is out of bounds.
+    pub fn split_layer_by_instrument(&mut self, index: usize) -> Range<usize> {
+        let mut instruments: BTreeMap<u8, BTreeMap<Tick, Note>> = BTreeMap::new();
+        for (tick, note) in std::mem::take(&mut self.layers[index].notes) {
+            instruments
+                .entry(note.instrument.into())
+                .or_default()
+                .insert(tick, note);
+        }
+        let count = instruments.len().max(1);
+        let mut instruments = instruments.into_iter();
+        if let Some((_, notes)) = instruments.next() {
+            self.layers[index].notes = notes;
+        }
+        for (offset, (_, notes)) in instruments.enumerate() {
+            let mut layer = self.layers[index].with_settings();
+            layer.notes = notes;
+            self.layers.insert(index + 1 + offset, layer);
+        }
+        index..index + count
+    }
+
+    /// Removes the layers without notes at the end and returns how many were removed.
+    pub fn remove_empty_trailing_layers(&mut self) -> usize {
+        let count = self.layers.len();
+        while let Some(layer) = self.layers.last() {
+            if !layer.notes.is_empty() {
+                break;
+            }
+            self.layers.pop();
+        }
+        count - self.layers.len()
     }
 
     pub fn decode<R>(reader: &mut R, header: &Header) -> Result<NoteBlocks, NbsError>
     where
         R: crate::ReadStringExt,
     {
+        NoteBlocks::decode_from(&mut Decoder::new(reader, DecodeLimits::default()), header)
+    }
+
+    pub(crate) fn decode_from<R>(
+        reader: &mut Decoder<R>,
+        header: &Header,
+    ) -> Result<NoteBlocks, NbsError>
+    where
+        R: std::io::Read,
+    {
+        reader.enter(Section::NoteBlocks);
+        reader.field("layer_count", |r| {
+            r.check_limit(Limit::Layers, header.layer_count.max(0) as usize)
+        })?;
+        // Layers are only created once their data is read, so a bogus layer count can not cause a huge allocation.
+        // Until then the notes are kept by the index of their layer.
+        let mut notes = BTreeMap::new();
+        let result = NoteBlocks::decode_notes(reader, header, &mut notes);
+        reader.recover(result, Repair::TruncatedNoteBlocks)?;
+        reader.enter(Section::Layers);
         let mut noteblocks = NoteBlocks::new();
-        // If the layer count differs from the encoded layers, NoteBlockStudio crashes.
-        for layer_index in 0..header.layer_count {
-            let layer = Layer::from_format(header.format);
-            noteblocks.layers.insert(layer_index as usize, layer);
+        let result = NoteBlocks::decode_layers(reader, header, &mut noteblocks, &mut notes);
+        reader.recover(result, Repair::MissingLayerInfo)?;
+        // Only reachable when decoding leniently, layers without information are added with default settings for their notes.
+        for (layer_index, notes) in notes {
+            if noteblocks.layers.len() <= layer_index {
+                noteblocks
+                    .layers
+                    .resize_with(layer_index + 1, || Layer::from_format(header.format));
+            }
+            noteblocks.layers[layer_index].notes = notes;
         }
+        Ok(noteblocks)
+    }
 
-        let mut tick: i16 = -1;
+    fn decode_notes<R>(
+        reader: &mut Decoder<R>,
+        header: &Header,
+        notes: &mut BTreeMap<usize, BTreeMap<Tick, Note>>,
+    ) -> Result<(), NbsError>
+    where
+        R: std::io::Read,
+    {
+        let vannila_instrument_count = header.vannila_instrument_count()?;
+        let mut layer_count = header.layer_count.max(0) as usize;
+
+        let mut tick: Tick = -1;
         loop {
-            let mut jumps = reader.read_i16::<LittleEndian>()?;
+            let jumps = reader.field("tick_jumps", |r| {
+                let jumps = r.read_i16::<LittleEndian>()?;
+                if jumps < 0 {
+                    return Err(NbsError::InvalidData("negative jump to the next tick"));
+                }
+                Ok(jumps)
+            })?;
             if jumps == 0 {
                 break;
             }
-            tick += jumps;
+            tick = reader.field("tick_jumps", |r| {
+                let tick = tick
+                    .checked_add(jumps.into())
+                    .ok_or(NbsError::InvalidData("tick overflow"))?;
+                r.check_limit(Limit::Ticks, tick as usize)?;
+                Ok(tick)
+            })?;
+            reader.tick = Some(tick);
+            reader.layer = None;
             let mut layer: i16 = -1;
             loop {
-                jumps = reader.read_i16::<LittleEndian>()?;
+                let jumps = reader.field("layer_jumps", |r| {
+                    let jumps = r.read_i16::<LittleEndian>()?;
+                    if jumps < 0 {
+                        return Err(NbsError::InvalidData("negative jump to the next layer"));
+                    }
+                    let next = layer
+                        .checked_add(jumps)
+                        .ok_or(NbsError::InvalidData("layer overflow"))?;
+                    if jumps != 0 && next as usize >= layer_count {
+                        if !r.lenient {
+                            return Err(NbsError::InvalidData(
+                                "note in a layer beyond the layer count",
+                            ));
+                        }
+                        r.check_limit(Limit::Layers, next as usize + 1)?;
+                    }
+                    Ok(jumps)
+                })?;
                 if jumps == 0 {
                     break;
                 }
                 layer += jumps;
-                let instrument = reader.read_i8()?;
+                reader.layer = Some(layer);
+                if layer as usize >= layer_count {
+                    // Only reachable when decoding leniently, the missing layers are added with default settings.
+                    reader.warn(
+                        "layer_jumps",
+                        Repair::AddedLayers(layer as usize + 1 - layer_count),
+                    );
+                    layer_count = layer as usize + 1;
+                }
+                let instrument = reader.field_u8("instrument")?;
 
-                let instrument = if instrument >= header.vannila_instrument_count()? {
+                let instrument = if instrument >= vannila_instrument_count {
                     Instrument::Custom(instrument)
                 } else {
                     Instrument::Vanilla(instrument)
                 };
-                let key = reader.read_i8()?;
+                let key = Key::from_raw(reader.field_u8("key")?);
                 let velocity = if header.format.version() >= 4 {
-                    Some(reader.read_i8()?)
+                    Some(Velocity::from_raw(reader.field_u8("velocity")?))
                 } else {
                     None
                 };
                 let panning = if header.format.version() >= 4 {
-                    Some(reader.read_i8()?)
+                    Some(Panning::from_raw(reader.field_u8("panning")?))
                 } else {
                     None
                 };
                 let pitch = if header.format.version() >= 4 {
-                    Some(reader.read_i16::<LittleEndian>()?)
+                    Some(reader.field_i16("pitch")?)
                 } else {
                     None
                 };
-                noteblocks
-                    .layers
-                    .get_mut(layer as usize)
-                    .unwrap()
-                    .notes
-                    .insert(
-                        tick,
-                        Note {
-                            instrument,
-                            key,
-                            velocity,
-                            panning,
-                            pitch,
-                        },
-                    );
+                notes.entry(layer as usize).or_default().insert(
+                    tick,
+                    Note {
+                        instrument,
+                        key,
+                        velocity,
+                        panning,
+                        pitch,
+                    },
+                );
             }
         }
-        for layer_index in 0..noteblocks.layers.len() {
-            let mut layer = noteblocks.layers.get_mut(layer_index).unwrap();
-            (*layer).name = reader.read_string()?;
-            if header.format.version() >= 4 {
-                (*layer).locked = Some(reader.read_i8()? == 1);
+        Ok(())
+    }
+
+    fn decode_layers<R>(
+        reader: &mut Decoder<R>,
+        header: &Header,
+        noteblocks: &mut NoteBlocks,
+        notes: &mut BTreeMap<usize, BTreeMap<Tick, Note>>,
+    ) -> Result<(), NbsError>
+    where
+        R: std::io::Read,
+    {
+        // If the layer count differs from the encoded layers, NoteBlockStudio crashes.
+        for layer_index in 0..header.layer_count.max(0) {
+            reader.layer = Some(layer_index);
+            let mut layer = Layer::from_format(header.format);
+            layer.name = reader.field_string("name")?;
+            layer.notes = notes.remove(&(layer_index as usize)).unwrap_or_default();
+            // When the rest of the information is missing, the name is kept.
+            noteblocks.layers.push(layer);
+            let layer = noteblocks.layers.last_mut().unwrap();
+            if header.format.version() >= 5 {
+                // Since version 5 this is the layer status: 0 = none, 1 = locked, 2 = solo.
+                let status = reader.field_i8("status")?;
+                layer.locked = Some(status == 1);
+                layer.solo = Some(status == 2);
+            } else if header.format.version() >= 4 {
+                layer.locked = Some(reader.field_i8("locked")? == 1);
             }
-            (*layer).volume = reader.read_i8()?;
+            layer.volume = Volume::from_raw(reader.field_u8("volume")?);
             if header.format.version() >= 2 {
-                (*layer).stereo = Some(reader.read_i8()?);
+                layer.stereo = Some(Panning::from_raw(reader.field_u8("stereo")?));
             }
         }
-        Ok(noteblocks)
+        Ok(())
     }
+
     pub fn encode<W>(&self, format: NbsFormat, writer: &mut W) -> Result<(), NbsError>
     where
         W: crate::WriteStringExt,
     {
-        let mut h_cursor: i16 = -1;
-        for note_index in 0..=self.calculate_length() {
+        // Layers are counted and jumped to with 16-bit numbers, so every layer index below fits into them.
+        i16::try_from(self.layers.len()).map_err(|_| NbsError::CountOutOfRange {
+            field: "layer_count",
+            count: self.layers.len(),
+        })?;
+        let mut h_cursor: Tick = -1;
+        for (tick, notes) in self.columns() {
+            // Only the jumps between ticks are 16-bit, the ticks they add up to are not limited.
+            // Notes before the first tick can not be reached, since every jump has to be positive.
+            let jump = i16::try_from(tick - h_cursor)
+                .ok()
+                .filter(|_| tick >= 0)
+                .ok_or(NbsError::TickOutOfRange {
+                    field: "tick_jumps",
+                    tick,
+                })?;
+            writer.write_i16::<LittleEndian>(jump)?;
+            h_cursor = tick;
             let mut v_cursor: i16 = -1;
-            let h_jumps = note_index - h_cursor;
-            let mut has_jumped_h = false;
-            for (layer_index, layer) in self.layers.iter().enumerate() {
-                if layer.notes.contains_key(&note_index) {
-                    let v_jumps = (layer_index as i16) - v_cursor;
-                    let note = layer.notes.get(&note_index).unwrap();
-                    if !has_jumped_h {
-                        writer.write_i16::<LittleEndian>(h_jumps)?;
-                        has_jumped_h = true;
-                        h_cursor += h_jumps;
-                    }
-                    writer.write_i16::<LittleEndian>(v_jumps)?;
-                    v_cursor += v_jumps;
-                    writer.write_i8(note.instrument.into())?;
-                    writer.write_i8(note.key)?;
-                    if format.version() >= 4 {
-                        writer.write_i8(note.velocity.ok_or(NbsError::InvalidFormat)?)?;
-                        writer.write_i8(note.panning.ok_or(NbsError::InvalidFormat)?)?;
-                        writer.write_i16::<LittleEndian>(
-                            note.pitch.ok_or(NbsError::InvalidFormat)?,
-                        )?;
-                    }
+            for (layer_index, note) in notes {
+                writer.write_i16::<LittleEndian>(layer_index as i16 - v_cursor)?;
+                v_cursor = layer_index as i16;
+                writer.write_u8(note.instrument.into())?;
+                writer.write_u8(note.key.into())?;
+                if format.version() >= 4 {
+                    writer.write_u8(note.velocity.ok_or(NbsError::InvalidFormat)?.into())?;
+                    writer.write_u8(note.panning.ok_or(NbsError::InvalidFormat)?.into())?;
+                    writer.write_i16::<LittleEndian>(note.pitch.ok_or(NbsError::InvalidFormat)?)?;
                 }
             }
-            if has_jumped_h {
-                // If this row actually had notes in it, that means we jumped at least 1 time, we indicate that its finished.
-                writer.write_i16::<LittleEndian>(0)?;
-            }
+            // Indicate that this tick is finished.
+            writer.write_i16::<LittleEndian>(0)?;
         }
         writer.write_i16::<LittleEndian>(0)?;
         for layer_index in 0..self.layers.len() {
             let layer = self.layers.get(layer_index).unwrap();
             writer.write_string(&layer.name)?;
-            if format.version() >= 4 {
+            if format.version() >= 5 {
+                writer.write_i8(if layer.solo.ok_or(NbsError::InvalidFormat)? {
+                    2
+                } else if layer.locked.ok_or(NbsError::InvalidFormat)? {
+                    1
+                } else {
+                    0
+                })?;
+            } else if format.version() >= 4 {
                 writer.write_i8(if layer.locked.ok_or(NbsError::InvalidFormat)? {
                     1
                 } else {
                     0
                 })?;
             }
-            writer.write_i8(layer.volume)?;
+            writer.write_u8(layer.volume.into())?;
             if format.version() >= 2 {
-                writer.write_i8(layer.stereo.ok_or(NbsError::InvalidFormat)?)?;
+                writer.write_u8(layer.stereo.ok_or(NbsError::InvalidFormat)?.into())?;
             }
         }
         Ok(())
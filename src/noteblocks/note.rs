@@ -1,34 +1,39 @@
-use super::instrument::Instrument;
+use super::{
+    instrument::Instrument,
+    value::{Key, Panning, Velocity},
+};
+use crate::{conversion::ConversionReport, NbsFormat};
 /// A Note is a Noteblock
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Note {
     /// The instrument of the note block.
     /// This is 0-15, or higher if the song uses custom instruments.
     pub instrument: Instrument,
     /// The key of the note block, from 0-87, where 0 is A0 and 87 is C8.
-    /// 33-57 is within the 2-octave limit.
-    pub key: i8,
+    /// 33-57 is within the 2-octave limit of vanilla note blocks, see the `vanilla` module.
+    pub key: Key,
     /// The velocity/volume of the note block, from 0% to 100%.
-    /// Only avabile in the new format version 4.
-    pub velocity: Option<i8>,
+    /// Only avabile in the new format since version 4.
+    pub velocity: Option<Velocity>,
     /// The stereo position of the note block, from 0-200.
     /// 100 is center panning.
-    /// Only avabile in the new format version 4.
-    pub panning: Option<i8>,
+    /// Only avabile in the new format since version 4.
+    pub panning: Option<Panning>,
     /// The fine pitch of the note block in cents.
     /// The max in Note Block Studio is limited to -1200 and +1200.
     /// 0 is no fine-tuning.
     /// ±100 cents is a single semitone difference.
-    /// Only avabile in the new format version 4.
+    /// Only avabile in the new format since version 4.
     pub pitch: Option<i16>,
 }
 
 impl Note {
     pub fn new(
         instrument: Instrument,
-        key: i8,
-        velocity: Option<i8>,
-        panning: Option<i8>,
+        key: Key,
+        velocity: Option<Velocity>,
+        panning: Option<Panning>,
         pitch: Option<i16>,
     ) -> Self {
         Note {
@@ -39,4 +44,30 @@ impl Note {
             pitch,
         }
     }
+
+    /// Fills or drops the fields that depend on the format, recording discarded values in the report.
+    /// When dropping the fine pitch, it is rounded to the nearest key.
+    pub fn convert_to(&mut self, format: NbsFormat, report: &mut ConversionReport) {
+        if format.version() >= 4 {
+            self.velocity.get_or_insert_with(Velocity::default);
+            self.panning.get_or_insert(Panning::CENTER);
+            self.pitch.get_or_insert(0);
+            return;
+        }
+        if self.velocity.take().unwrap_or_default() != Velocity::default() {
+            report.velocity += 1;
+        }
+        if self.panning.take().unwrap_or_default() != Panning::CENTER {
+            report.panning += 1;
+        }
+        let pitch = self.pitch.take().unwrap_or(0);
+        if pitch % 100 != 0 {
+            report.fine_pitch += 1;
+        }
+        let key = self.key.get() as i32 + (pitch as f32 / 100.0).round() as i32;
+        if !(0..=Key::MAX.get() as i32).contains(&key) {
+            report.clamped_keys += 1;
+        }
+        self.key = Key::saturating(key);
+    }
 }
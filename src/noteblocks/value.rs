@@ -0,0 +1,114 @@
+//! The value types of notes and layers.
+//!
+//! Each value is stored as a single unsigned byte, just like in NBS files.
+//! `new` only accepts values within the range Note Block Studio uses, while `from_raw` keeps any byte,
+//! so files with out of range values still decode to the same bytes. `Nbs::validate` reports such values.
+//!
+//! ```rust
+//! use nbs::noteblocks::value::{Key, Panning};
+//!
+//! assert_eq!(Key::new(45), Some(Key::default()));
+//! assert_eq!(Key::new(88), None);
+//! assert_eq!(Key::saturating(100), Key::MAX);
+//! assert!(!Panning::from_raw(255).is_valid());
+//! ```
+
+use std::fmt;
+
+macro_rules! value {
+    ($(#[$doc:meta])* $name:ident, max: $max:expr, default: $default:expr) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(feature = "serde", serde(transparent))]
+        pub struct $name(u8);
+
+        impl $name {
+            /// The lowest valid value.
+            pub const MIN: $name = $name(0);
+            /// The highest valid value.
+            pub const MAX: $name = $name($max);
+
+            /// Returns the value if it is within range.
+            pub fn new(value: u8) -> Option<$name> {
+                if value <= $max {
+                    Some($name(value))
+                } else {
+                    None
+                }
+            }
+
+            /// Returns the value, clamped into range.
+            pub fn saturating(value: i32) -> $name {
+                $name(value.clamp(0, $max) as u8)
+            }
+
+            /// Keeps any byte, like one read from a file, even if it is out of range.
+            pub const fn from_raw(value: u8) -> $name {
+                $name(value)
+            }
+
+            pub fn get(self) -> u8 {
+                self.0
+            }
+
+            /// Returns false for out of range values created by `from_raw`.
+            pub fn is_valid(self) -> bool {
+                self.0 <= $max
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name($default)
+            }
+        }
+
+        impl From<$name> for u8 {
+            fn from(value: $name) -> u8 {
+                value.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+value!(
+    /// The key of a note, from 0-87, where 0 is A0 and 87 is C8.
+    /// The default is 45 (F#4), the key vanilla sounds are played at without any pitch change.
+    Key,
+    max: 87,
+    default: 45
+);
+
+value!(
+    /// The velocity of a note, from 0% to 100%.
+    Velocity,
+    max: 100,
+    default: 100
+);
+
+value!(
+    /// The stereo position of a note or layer, from 0 (left) to 200 (right).
+    /// The default is 100, which is center panning.
+    Panning,
+    max: 200,
+    default: 100
+);
+
+value!(
+    /// The volume of a layer, from 0% to 100%.
+    Volume,
+    max: 100,
+    default: 100
+);
+
+impl Panning {
+    /// Center panning.
+    pub const CENTER: Panning = Panning(100);
+}
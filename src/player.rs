@@ -0,0 +1,300 @@
+//! Real-time playback of songs.
+//!
+//! The `Player` does not make any sound itself, it tells you which notes are due whenever you poll it.
+//! Time is taken from a `Clock`, so the player can follow the system time, a game tick loop or a test.
+//!
+//! ## Example: Driving a player from a game loop
+//!
+//! ```rust
+//! use nbs::{
+//!     player::{ManualClock, Player},
+//!     Nbs,
+//! };
+//! use std::{fs::File, time::Duration};
+//!
+//! let nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let mut player = Player::with_clock(&nbs, ManualClock::new());
+//! player.play();
+//! while !player.is_stopped() {
+//!     for (tick, layer_index, note) in player.poll() {
+//!         // Play the note in game.
+//!     }
+//!     // Every game tick lasts 50 milliseconds.
+//!     player.clock().advance(Duration::from_millis(50));
+//! }
+//! ```
+
+use crate::{
+    noteblocks::{instrument::Instrument, note::Note},
+    tempo::TempoMap,
+    Nbs, Tick,
+};
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+/// A source of time for the `Player`.
+pub trait Clock {
+    /// Returns the time that has passed since some fixed point, which must never decrease.
+    fn now(&self) -> Duration;
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now(&self) -> Duration {
+        (**self).now()
+    }
+}
+
+/// A clock that follows the system time.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock that only moves when it is advanced, for driving a player from a game loop or a test.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Cell<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock::default()
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
+
+/// Whether the player is playing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerState {
+    Playing,
+    Paused,
+    /// Stopped by the user or because the song has ended.
+    Stopped,
+}
+
+/// Plays a song in real time, following the tempo changes and the loop settings of the song.
+/// Tempo changer notes are not emitted.
+/// Layers that are not soloed are skipped while at least one layer is soloed.
+pub struct Player<'a, C: Clock = SystemClock> {
+    nbs: &'a Nbs,
+    tempo_map: TempoMap,
+    /// Notes of this instrument change the tempo and are not emitted.
+    tempo_changer: Option<Instrument>,
+    clock: C,
+    state: PlayerState,
+    /// The position in the song when the player was last started, seeked or looped.
+    position: Duration,
+    /// The time of the clock at `position`.
+    anchor: Duration,
+    /// The next tick whose notes have not been emitted yet.
+    next_tick: Tick,
+    /// The amount of times the song has looped.
+    loops: u32,
+}
+
+impl<'a> Player<'a, SystemClock> {
+    /// Creates a stopped player that follows the system time.
+    pub fn new(nbs: &'a Nbs) -> Self {
+        Player::with_clock(nbs, SystemClock::new())
+    }
+}
+
+impl<'a, C: Clock> Player<'a, C> {
+    /// Creates a stopped player that takes its time from the given clock.
+    pub fn with_clock(nbs: &'a Nbs, clock: C) -> Self {
+        Player {
+            nbs,
+            tempo_map: nbs.tempo_map(),
+            tempo_changer: nbs.custom_instruments.tempo_changer(),
+            clock,
+            state: PlayerState::Stopped,
+            position: Duration::ZERO,
+            anchor: Duration::ZERO,
+            next_tick: 0,
+            loops: 0,
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    pub fn state(&self) -> PlayerState {
+        self.state
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state == PlayerState::Stopped
+    }
+
+    /// Returns the amount of times the song has looped since it was started.
+    pub fn loops(&self) -> u32 {
+        self.loops
+    }
+
+    /// Starts playing, from the beginning if the player was stopped or where it was paused otherwise.
+    pub fn play(&mut self) {
+        match self.state {
+            PlayerState::Playing => return,
+            PlayerState::Paused => {}
+            PlayerState::Stopped => {
+                self.position = Duration::ZERO;
+                self.next_tick = 0;
+                self.loops = 0;
+            }
+        }
+        self.anchor = self.clock.now();
+        self.state = PlayerState::Playing;
+    }
+
+    /// Pauses playback, keeping the position.
+    pub fn pause(&mut self) {
+        if self.state == PlayerState::Playing {
+            self.position = self.position();
+            self.state = PlayerState::Paused;
+        }
+    }
+
+    /// Stops playback, the next call to `play` starts from the beginning.
+    pub fn stop(&mut self) {
+        self.state = PlayerState::Stopped;
+    }
+
+    /// Jumps to a tick, the notes of that tick are emitted by the next poll.
+    /// A stopped player is paused at the tick.
+    pub fn seek(&mut self, tick: Tick) {
+        let tick = tick.max(0);
+        self.position = self.tempo_map.tick_to_time(tick);
+        self.anchor = self.clock.now();
+        self.next_tick = tick;
+        if self.state == PlayerState::Stopped {
+            self.loops = 0;
+            self.state = PlayerState::Paused;
+        }
+    }
+
+    /// Returns the current position in the song.
+    pub fn position(&self) -> Duration {
+        match self.state {
+            PlayerState::Playing => self.position + self.clock.now().saturating_sub(self.anchor),
+            _ => self.position,
+        }
+    }
+
+    /// Returns the current tick of the song.
+    pub fn tick(&self) -> Tick {
+        self.tempo_map.time_to_tick(self.position()).floor() as Tick
+    }
+
+    /// Returns every note that became due since the last poll as `(tick, layer_index, &Note)`, ordered by tick and then by layer.
+    /// Loops the song according to its loop settings and stops the player at the end of the song.
+    pub fn poll(&mut self) -> Vec<(Tick, usize, &'a Note)> {
+        let mut events = Vec::new();
+        if self.state != PlayerState::Playing {
+            return events;
+        }
+        let nbs = self.nbs;
+        let now = self.clock.now();
+        let mut position = self.position();
+        // The song ends after the duration of its last tick.
+        let (end_tick, end) = if nbs.noteblocks.layers.iter().any(|l| !l.notes.is_empty()) {
+            let last_tick = nbs.song_ticks();
+            (
+                last_tick.saturating_add(1),
+                Duration::from_secs_f64(self.tempo_map.tick_to_seconds(last_tick as f64 + 1.0)),
+            )
+        } else {
+            (0, Duration::ZERO)
+        };
+        loop {
+            let mut last_tick =
+                (self.tempo_map.time_to_tick(position).floor() as Tick).min(end_tick - 1);
+            // Correct rounding errors, so a tick is due exactly when its time is reached.
+            while last_tick + 1 < end_tick && self.tempo_map.tick_to_time(last_tick + 1) <= position
+            {
+                last_tick += 1;
+            }
+            while last_tick >= self.next_tick && self.tempo_map.tick_to_time(last_tick) > position {
+                last_tick -= 1;
+            }
+            if last_tick >= self.next_tick {
+                self.emit(self.next_tick..=last_tick, &mut events);
+                self.next_tick = last_tick + 1;
+            }
+            if position < end {
+                break;
+            }
+            let loop_start = nbs.header.loop_start_tick.unwrap_or(0).max(0);
+            let loop_duration = end.saturating_sub(self.tempo_map.tick_to_time(loop_start));
+            let max_loops = nbs.header.max_loop_count.unwrap_or(0) as u8 as u32;
+            if nbs.header.is_loop == Some(true)
+                && (max_loops == 0 || self.loops < max_loops)
+                && loop_duration > Duration::ZERO
+            {
+                self.loops += 1;
+                position -= loop_duration;
+                self.next_tick = loop_start;
+            } else {
+                self.state = PlayerState::Stopped;
+                self.position = end;
+                return events;
+            }
+        }
+        self.position = position;
+        self.anchor = now;
+        events
+    }
+
+    fn emit(
+        &self,
+        ticks: std::ops::RangeInclusive<Tick>,
+        events: &mut Vec<(Tick, usize, &'a Note)>,
+    ) {
+        let layers = &self.nbs.noteblocks.layers;
+        let solo = layers.iter().any(|layer| layer.solo == Some(true));
+        let start = events.len();
+        for (layer_index, layer) in layers.iter().enumerate() {
+            if solo && layer.solo != Some(true) {
+                continue;
+            }
+            for (&tick, note) in layer.notes.range(ticks.clone()) {
+                if Some(note.instrument) != self.tempo_changer {
+                    events.push((tick, layer_index, note));
+                }
+            }
+        }
+        events[start..].sort_by_key(|&(tick, layer_index, _)| (tick, layer_index));
+    }
+}
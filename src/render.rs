@@ -0,0 +1,293 @@
+//! Offline rendering of songs into WAV audio.
+//!
+//! Note Block Studio plays every instrument by resampling a single sound file, which is what the [`Renderer`] does as well.
+//! No sounds are bundled with this crate, a [`Sample`] has to be provided for every instrument that should be audible.
+//! Notes of instruments without a sample are skipped.
+//!
+//! ## Example: Rendering a song
+//!
+//! ```rust
+//! use nbs::{
+//!     noteblocks::instrument,
+//!     render::{Renderer, Sample, WavFormat},
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let mut renderer = Renderer::new(44100);
+//! // A short sine wave at F#4 standing in for the real piano sound.
+//! let sine = (0..22050)
+//!     .map(|i| (i as f32 * 369.99 * std::f32::consts::TAU / 44100.0).sin() * 0.25)
+//!     .collect();
+//! renderer.set_sample(instrument::PIANO, Sample::new(44100, sine));
+//! let mut wav = Vec::new();
+//! renderer.encode_wav(&nbs, WavFormat::Pcm16, &mut wav).unwrap();
+//! ```
+
+use crate::{
+    noteblocks::{instrument::Instrument, layer::Layer, note::Note, value::Key},
+    Nbs, NbsError,
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{collections::HashMap, convert::TryFrom, io::Read};
+
+/// The key at which a vanilla sound is played without any pitch change (F#4).
+pub const BASE_KEY: Key = Key::from_raw(45);
+
+/// The highest fine pitch in cents Note Block Studio allows, the lowest is its negative.
+const MAX_FINE_PITCH: i16 = 1200;
+
+/// A mono sound that gets resampled to play notes.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// The sample rate of `data` in Hz.
+    pub sample_rate: u32,
+    /// The samples, ranging from -1.0 to 1.0.
+    pub data: Vec<f32>,
+}
+
+impl Sample {
+    pub fn new(sample_rate: u32, data: Vec<f32>) -> Self {
+        Sample { sample_rate, data }
+    }
+
+    /// Decode a WAV buffer.
+    /// Supports 8, 16, 24 and 32 bit PCM and 32 bit float, multiple channels are mixed down to mono.
+    pub fn decode_wav<R>(reader: &mut R) -> Result<Sample, NbsError>
+    where
+        R: Read,
+    {
+        let mut id = [0u8; 4];
+        reader.read_exact(&mut id)?;
+        if &id != b"RIFF" {
+            return Err(NbsError::InvalidFormat);
+        }
+        reader.read_u32::<LittleEndian>()?;
+        reader.read_exact(&mut id)?;
+        if &id != b"WAVE" {
+            return Err(NbsError::InvalidFormat);
+        }
+        let mut format = None;
+        loop {
+            reader.read_exact(&mut id)?;
+            let size = reader.read_u32::<LittleEndian>()? as usize;
+            let mut chunk = Vec::new();
+            reader.take(size as u64).read_to_end(&mut chunk)?;
+            if chunk.len() != size {
+                return Err(NbsError::InvalidFormat);
+            }
+            if size % 2 == 1 {
+                reader.read_u8()?;
+            }
+            match &id {
+                b"fmt " => {
+                    let mut chunk = &chunk[..];
+                    let tag = chunk.read_u16::<LittleEndian>()?;
+                    let channels = chunk.read_u16::<LittleEndian>()?;
+                    let sample_rate = chunk.read_u32::<LittleEndian>()?;
+                    chunk.read_u32::<LittleEndian>()?;
+                    chunk.read_u16::<LittleEndian>()?;
+                    let bits = chunk.read_u16::<LittleEndian>()?;
+                    format = Some((tag, channels, sample_rate, bits));
+                }
+                b"data" => {
+                    let (tag, channels, sample_rate, bits) =
+                        format.ok_or(NbsError::InvalidFormat)?;
+                    if channels == 0 || sample_rate == 0 {
+                        return Err(NbsError::InvalidFormat);
+                    }
+                    let mut data = &chunk[..];
+                    let mut frame = Vec::with_capacity(channels as usize);
+                    let mut samples = Vec::new();
+                    let frame_size = channels as usize * (bits as usize / 8);
+                    for _ in 0..chunk.len() / frame_size.max(1) {
+                        frame.clear();
+                        for _ in 0..channels {
+                            frame.push(match (tag, bits) {
+                                (1, 8) => (data.read_u8()? as f32 - 128.0) / 128.0,
+                                (1, 16) => data.read_i16::<LittleEndian>()? as f32 / 32768.0,
+                                (1, 24) => data.read_i24::<LittleEndian>()? as f32 / 8388608.0,
+                                (1, 32) => data.read_i32::<LittleEndian>()? as f32 / 2147483648.0,
+                                (3, 32) => data.read_f32::<LittleEndian>()?,
+                                _ => return Err(NbsError::InvalidFormat),
+                            });
+                        }
+                        samples.push(frame.iter().sum::<f32>() / channels as f32);
+                    }
+                    return Ok(Sample::new(sample_rate, samples));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The sample format of an encoded WAV buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WavFormat {
+    /// 16 bit signed integer PCM.
+    Pcm16,
+    /// 32 bit IEEE float.
+    Float32,
+}
+
+/// Renders songs into stereo audio using the samples provided for each instrument.
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    sample_rate: u32,
+    samples: HashMap<Instrument, Sample>,
+}
+
+impl Renderer {
+    /// Creates a new Renderer without any samples, producing audio at the given sample rate.
+    pub fn new(sample_rate: u32) -> Self {
+        Renderer {
+            sample_rate,
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Sets the sample used to play notes of the given instrument.
+    /// Vanilla samples are expected to sound at F#4, custom samples at the key stored in their `CustomInstrumentInfo`.
+    pub fn set_sample(&mut self, instrument: Instrument, sample: Sample) {
+        self.samples.insert(instrument, sample);
+    }
+
+    /// Returns the sample rate of the rendered audio.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Renders the song into interleaved stereo frames, `[left, right]`.
+    /// The frames are not clipped, so they may exceed the range -1.0 to 1.0 when many notes play at once.
+    pub fn render(&self, nbs: &Nbs) -> Vec<[f32; 2]> {
+        let mut frames: Vec<[f32; 2]> = Vec::new();
+        if nbs.header.song_tempo <= 0 || self.sample_rate == 0 {
+            return frames;
+        }
+        let tempo_map = nbs.tempo_map();
+        let tempo_changer = nbs.custom_instruments.tempo_changer();
+        for layer in &nbs.noteblocks.layers {
+            for (tick, note) in &layer.notes {
+                if Some(note.instrument) == tempo_changer {
+                    continue;
+                }
+                let sample = match self.samples.get(&note.instrument) {
+                    Some(sample) if !sample.data.is_empty() && sample.sample_rate > 0 => sample,
+                    _ => continue,
+                };
+                let base_key = if note.instrument.is_custom() {
+                    nbs.custom_instruments
+                        .get(note.instrument)
+                        .map(|info| info.pitch)
+                        .unwrap_or(BASE_KEY)
+                } else {
+                    BASE_KEY
+                };
+                let start =
+                    (tempo_map.tick_to_seconds(*tick as f64) * self.sample_rate as f64) as usize;
+                self.mix(&mut frames, start, sample, base_key, layer, note);
+            }
+        }
+        frames
+    }
+
+    /// Renders the song and encodes it as a stereo WAV buffer.
+    pub fn encode_wav<W>(
+        &self,
+        nbs: &Nbs,
+        format: WavFormat,
+        writer: &mut W,
+    ) -> Result<(), NbsError>
+    where
+        W: WriteBytesExt,
+    {
+        let frames = self.render(nbs);
+        let (tag, bits): (u16, u16) = match format {
+            WavFormat::Pcm16 => (1, 16),
+            WavFormat::Float32 => (3, 32),
+        };
+        let block_align = 2 * bits / 8;
+        // The sizes of the RIFF and data chunks are 32-bit.
+        let data_size = u32::try_from(frames.len())
+            .ok()
+            .and_then(|frames| frames.checked_mul(block_align as u32))
+            .filter(|data_size| data_size.checked_add(36).is_some())
+            .ok_or(NbsError::InvalidData("the song is too long for a WAV file"))?;
+        let byte_rate = self
+            .sample_rate
+            .checked_mul(block_align as u32)
+            .ok_or(NbsError::InvalidData("the sample rate is too high"))?;
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(36 + data_size)?;
+        writer.write_all(b"WAVE")?;
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(16)?;
+        writer.write_u16::<LittleEndian>(tag)?;
+        writer.write_u16::<LittleEndian>(2)?;
+        writer.write_u32::<LittleEndian>(self.sample_rate)?;
+        writer.write_u32::<LittleEndian>(byte_rate)?;
+        writer.write_u16::<LittleEndian>(block_align)?;
+        writer.write_u16::<LittleEndian>(bits)?;
+        writer.write_all(b"data")?;
+        writer.write_u32::<LittleEndian>(data_size)?;
+        for frame in frames {
+            for value in frame.iter() {
+                let value = value.clamp(-1.0, 1.0);
+                match format {
+                    WavFormat::Pcm16 => {
+                        writer.write_i16::<LittleEndian>((value * 32767.0) as i16)?
+                    }
+                    WavFormat::Float32 => writer.write_f32::<LittleEndian>(value)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn mix(
+        &self,
+        frames: &mut Vec<[f32; 2]>,
+        start: usize,
+        sample: &Sample,
+        base_key: Key,
+        layer: &Layer,
+        note: &Note,
+    ) {
+        // Limited to the range of Note Block Studio, so a broken file can not stretch the sample endlessly.
+        let pitch = note
+            .pitch
+            .unwrap_or(0)
+            .clamp(-MAX_FINE_PITCH, MAX_FINE_PITCH);
+        let semitones = (note.key.get() as f64 - base_key.get() as f64) + pitch as f64 / 100.0;
+        // How far to advance in the sample for every rendered frame.
+        let step =
+            2f64.powf(semitones / 12.0) * sample.sample_rate as f64 / self.sample_rate as f64;
+        let length = (sample.data.len() as f64 / step) as usize;
+        let gain = note.velocity.unwrap_or_default().get() as f32 / 100.0
+            * layer.volume.get() as f32
+            / 100.0;
+        // Panning ranges from 0 (left) to 200 (right), the note and layer panning are averaged.
+        let note_panning = note.panning.unwrap_or_default().get() as f32;
+        let layer_panning = layer.stereo.unwrap_or_default().get() as f32;
+        // Out of range values are clamped, so neither side gets a negative gain.
+        let pan = (((note_panning + layer_panning) / 2.0 - 100.0) / 100.0).clamp(-1.0, 1.0);
+        let left = gain * (1.0 - pan).min(1.0);
+        let right = gain * (1.0 + pan).min(1.0);
+
+        if frames.len() < start + length {
+            frames.resize(start + length, [0.0, 0.0]);
+        }
+        for (i, frame) in frames[start..start + length].iter_mut().enumerate() {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = sample.data.get(index).copied().unwrap_or(0.0);
+            let next = sample.data.get(index + 1).copied().unwrap_or(0.0);
+            let value = current + (next - current) * fraction;
+            frame[0] += value * left;
+            frame[1] += value * right;
+        }
+    }
+}
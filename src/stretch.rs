@@ -0,0 +1,195 @@
+//! Re-gridding songs to a different tempo, while keeping their duration.
+//!
+//! Stretching by a factor multiplies every tick and every tempo by it, so each note is still played at the same time.
+//! Notes that do not land on a whole tick are rounded, which can move two notes of a layer onto the same tick.
+//!
+//! ## Example: Re-gridding a song to 20 ticks per second
+//!
+//! ```rust
+//! use nbs::{
+//!     stretch::{Collision, Stretch},
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let mut nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let stretch = Stretch {
+//!     collision: Collision::KeepLast,
+//!     ..Stretch::to_tempo(&nbs, 20.0)
+//! };
+//! let report = nbs.stretch(&stretch).unwrap();
+//! println!("{} notes were rounded, {} were dropped", report.rounded, report.dropped);
+//! assert_eq!(nbs.header.song_tempo, 2000);
+//! ```
+
+use crate::{
+    noteblocks::{instrument::Instrument, note::Note},
+    tempo::MIN_TICKS_PER_SECOND,
+    Nbs, NbsError, Tick,
+};
+use std::collections::BTreeMap;
+
+/// How ticks that are not whole after stretching are rounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Rounds to the nearest tick.
+    Nearest,
+    /// Rounds to the earlier tick.
+    Down,
+    /// Rounds to the later tick.
+    Up,
+}
+
+/// What happens when two notes of a layer land on the same tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// The note that was played first is kept.
+    KeepFirst,
+    /// The note that was played last is kept.
+    KeepLast,
+    /// Nothing is changed and `NbsError::NoteCollision` is returned.
+    Error,
+}
+
+/// Describes how a song is stretched.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stretch {
+    /// The factor every tick and tempo is multiplied by, above 1 makes the grid finer.
+    pub factor: f64,
+    /// `Nearest` by default.
+    pub rounding: Rounding,
+    /// `Error` by default.
+    pub collision: Collision,
+}
+
+impl Stretch {
+    /// Stretches a song by a factor.
+    pub fn factor(factor: f64) -> Self {
+        Stretch {
+            factor,
+            rounding: Rounding::Nearest,
+            collision: Collision::Error,
+        }
+    }
+
+    /// Stretches a song so its tempo becomes `ticks_per_second`.
+    /// Tempo changes keep their ratio to the tempo of the song.
+    pub fn to_tempo(nbs: &Nbs, ticks_per_second: f64) -> Self {
+        let tempo = (nbs.header.song_tempo as f64 / 100.0).max(MIN_TICKS_PER_SECOND);
+        Stretch::factor(ticks_per_second / tempo)
+    }
+
+    /// Returns the stretched tempo, in the format of `Header::song_tempo`.
+    pub(crate) fn tempo(&self, song_tempo: i16) -> Result<i16, NbsError> {
+        if !(self.factor.is_finite() && self.factor > 0.0) {
+            return Err(NbsError::InvalidData("the stretch factor is not positive"));
+        }
+        let song_tempo = (song_tempo as f64 * self.factor).round();
+        if song_tempo > i16::MAX as f64 {
+            return Err(NbsError::InvalidData("the stretched tempo is too high"));
+        }
+        // Like elsewhere, tempos below `MIN_TICKS_PER_SECOND` are raised to it.
+        Ok(song_tempo.max(MIN_TICKS_PER_SECOND * 100.0) as i16)
+    }
+
+    /// Returns the stretched tick, and whether it had to be rounded.
+    pub(crate) fn tick(&self, tick: Tick) -> (Tick, bool) {
+        let exact = tick as f64 * self.factor;
+        let rounded = match self.rounding {
+            Rounding::Nearest => exact.round(),
+            Rounding::Down => exact.floor(),
+            Rounding::Up => exact.ceil(),
+        };
+        (rounded as Tick, rounded != exact)
+    }
+}
+
+/// Describes what `Nbs::stretch` changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StretchReport {
+    /// Amount of notes that did not land on a whole tick and were rounded.
+    pub rounded: usize,
+    /// Amount of notes that were removed, because another note of their layer landed on the same tick.
+    pub dropped: usize,
+    /// Amount of tempo changers whose tempo was stretched.
+    pub tempo_changes: usize,
+}
+
+/// Stretches the ticks of all notes, the tempo of the song and its tempo changers.
+pub(crate) fn stretch(nbs: &mut Nbs, stretch: &Stretch) -> Result<StretchReport, NbsError> {
+    let song_tempo = stretch.tempo(nbs.header.song_tempo)?;
+    let tempo_changer = nbs.custom_instruments.tempo_changer();
+    let mut layers: Vec<_> = nbs
+        .noteblocks
+        .layers
+        .iter_mut()
+        .map(|layer| &mut layer.notes)
+        .collect();
+    let report = stretch_notes(&mut layers, stretch, tempo_changer)?;
+    nbs.header.song_tempo = song_tempo;
+    if let Some(loop_start_tick) = &mut nbs.header.loop_start_tick {
+        *loop_start_tick = stretch.tick(*loop_start_tick).0;
+    }
+    nbs.sync_header();
+    Ok(report)
+}
+
+/// Stretches the ticks of the notes of every layer and the tempo of the tempo changers among them.
+/// Nothing is changed when an error is returned.
+pub(crate) fn stretch_notes(
+    layers: &mut [&mut BTreeMap<Tick, Note>],
+    stretch: &Stretch,
+    tempo_changer: Option<Instrument>,
+) -> Result<StretchReport, NbsError> {
+    // Find the collisions first, so nothing is changed when one of them is an error.
+    // Every layer gets a list of `(tick, new_tick)` of the notes that are kept.
+    let mut report = StretchReport::default();
+    let mut layer_ticks = Vec::with_capacity(layers.len());
+    for (layer_index, notes) in layers.iter().enumerate() {
+        let mut ticks: Vec<(Tick, Tick)> = Vec::with_capacity(notes.len());
+        for &tick in notes.keys() {
+            let (new_tick, rounded) = stretch.tick(tick);
+            if rounded {
+                report.rounded += 1;
+            }
+            // Stretching keeps the order of the ticks, so only the previous note can be on the same tick.
+            match ticks.last_mut() {
+                Some(previous) if previous.1 == new_tick => {
+                    report.dropped += 1;
+                    match stretch.collision {
+                        Collision::KeepFirst => {}
+                        Collision::KeepLast => previous.0 = tick,
+                        Collision::Error => {
+                            return Err(NbsError::NoteCollision {
+                                tick: new_tick,
+                                layer: layer_index,
+                            })
+                        }
+                    }
+                }
+                _ => ticks.push((tick, new_tick)),
+            }
+        }
+        layer_ticks.push(ticks);
+    }
+    for (layer, ticks) in layers.iter_mut().zip(layer_ticks) {
+        let mut notes = std::mem::take(&mut **layer);
+        for (tick, new_tick) in ticks {
+            if let Some(mut note) = notes.remove(&tick) {
+                match note.pitch {
+                    // The sign of the pitch is kept, the tempo is its magnitude.
+                    Some(pitch) if Some(note.instrument) == tempo_changer && pitch != 0 => {
+                        let bpm = (pitch.unsigned_abs() as f64 * stretch.factor).round();
+                        // A pitch of 0 would turn the tempo change off.
+                        let bpm = bpm.clamp(1.0, i16::MAX as f64) as i16;
+                        note.pitch = Some(bpm * pitch.signum());
+                        report.tempo_changes += 1;
+                    }
+                    _ => {}
+                }
+                layer.insert(new_tick, note);
+            }
+        }
+    }
+    Ok(report)
+}
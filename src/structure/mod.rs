@@ -0,0 +1,455 @@
+//! Export of songs as Minecraft builds of note blocks and redstone.
+//!
+//! The build is a line of repeaters running east, started by a button at its west end.
+//! Every tick with notes branches off the line to the south and north, where each note block sits on the block that selects its instrument, with redstone dust on top.
+//! A branch holds 14 note blocks per side before a repeater refreshes the signal, which plays the note blocks behind it one redstone tick later.
+//!
+//! Redstone updates 10 times per second, so every tick is built as a whole amount of redstone ticks, see [`redstone_ticks_per_tick`].
+//! Keys outside of the range of a note block are moved by whole octaves and the fine pitch is rounded, use `vanilla::fold` beforehand to choose how.
+//! Notes with custom instruments are skipped.
+//!
+//! The build can be written as a Sponge Schematic (version 2 or 3, `.schem`) for WorldEdit and similar tools,
+//! or as a vanilla structure file (`.nbt`) for structure blocks and `/place template`.
+//!
+//! ## Example: Exporting a schematic
+//!
+//! ```rust
+//! use nbs::{
+//!     structure::{self, SchematicVersion},
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let mut schem = Vec::new();
+//! structure::encode_schematic(&nbs, SchematicVersion::V3, &mut schem).unwrap();
+//! let mut nbt = Vec::new();
+//! structure::encode_structure(&nbs, &mut nbt).unwrap();
+//! ```
+
+mod nbt;
+
+use self::nbt::Tag;
+use crate::{
+    archive,
+    noteblocks::instrument::{self, Instrument},
+    vanilla, Nbs, NbsError, Tick,
+};
+use std::{collections::HashMap, fmt, io::Write};
+
+/// The data version written to the files, which is the one of Minecraft 1.16.5.
+pub const DATA_VERSION: i32 = 2586;
+
+/// The version of a Sponge Schematic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchematicVersion {
+    /// Version 2, read by WorldEdit 7.2 and most other tools.
+    V2,
+    /// Version 3, read by WorldEdit 7.3 and newer.
+    V3,
+}
+
+/// The longest delay of a single repeater, in redstone ticks.
+const MAX_REPEATER_DELAY: u64 = 4;
+/// Amount of note blocks on each side of a branch before a repeater refreshes the signal.
+const BRANCH_SEGMENT: usize = 14;
+
+/// Height of the blocks below the note blocks.
+const FLOOR: usize = 0;
+/// Height of the instrument blocks.
+const INSTRUMENT: usize = 1;
+/// Height of the note blocks and of the blocks that support the redstone line.
+const NOTE_BLOCK: usize = 2;
+/// Height of the redstone dust and the repeaters.
+const REDSTONE: usize = 3;
+
+/// Returns the amount of redstone ticks a tick is built with at the given tempo.
+/// Redstone updates 10 times per second, so only 10, 5, 3.33, 2.5... ticks per second are exact, other tempos are rounded to the nearest of those.
+pub fn redstone_ticks_per_tick(ticks_per_second: f64) -> u32 {
+    (10.0 / ticks_per_second).round().max(1.0) as u32
+}
+
+/// Returns the block that makes a note block play the instrument.
+fn instrument_block(instrument: Instrument) -> &'static str {
+    match instrument {
+        instrument::DOUBLE_BASS => "oak_planks",
+        instrument::BASS_DRUM => "stone",
+        instrument::SNARE_DRUM => "sand",
+        instrument::CLICK => "glass",
+        instrument::GUITAR => "white_wool",
+        instrument::FLUTE => "clay",
+        instrument::BELL => "gold_block",
+        instrument::CHIME => "packed_ice",
+        instrument::XYLOPHONE => "bone_block",
+        instrument::IRON_XYLOPHONE => "iron_block",
+        instrument::COW_BELL => "soul_sand",
+        instrument::DIDGERIDOO => "pumpkin",
+        instrument::BIT => "emerald_block",
+        instrument::BANJO => "hay_block",
+        instrument::PLING => "glowstone",
+        // Any block that is not listed plays the harp.
+        _ => "dirt",
+    }
+}
+
+/// The instrument block, the in-game instrument and the note of a note block.
+type NoteBlock = (&'static str, &'static str, u8);
+
+/// A block with its properties, which are kept in alphabetical order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BlockState {
+    name: &'static str,
+    properties: Vec<(&'static str, String)>,
+}
+
+impl BlockState {
+    fn new(name: &'static str) -> Self {
+        BlockState {
+            name,
+            properties: Vec::new(),
+        }
+    }
+
+    fn with<T: ToString>(mut self, key: &'static str, value: T) -> Self {
+        self.properties.push((key, value.to_string()));
+        self
+    }
+
+    fn repeater(delay: u64, facing: &str) -> Self {
+        BlockState::new("repeater")
+            .with("delay", delay)
+            .with("facing", facing)
+            .with("locked", false)
+            .with("powered", false)
+    }
+
+    fn redstone_wire(east: bool, north: bool, south: bool, west: bool) -> Self {
+        let side = |connected| if connected { "side" } else { "none" };
+        BlockState::new("redstone_wire")
+            .with("east", side(east))
+            .with("north", side(north))
+            .with("power", 0)
+            .with("south", side(south))
+            .with("west", side(west))
+    }
+
+    /// Returns the palette entry of a vanilla structure.
+    fn to_tag(&self) -> Tag {
+        let mut entries = vec![("Name".to_string(), Tag::String(self.id()))];
+        if !self.properties.is_empty() {
+            let properties = self
+                .properties
+                .iter()
+                .map(|(key, value)| (key.to_string(), Tag::String(value.clone())))
+                .collect();
+            entries.push(("Properties".to_string(), Tag::Compound(properties)));
+        }
+        Tag::Compound(entries)
+    }
+
+    fn id(&self) -> String {
+        format!("minecraft:{}", self.name)
+    }
+}
+
+impl fmt::Display for BlockState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "minecraft:{}", self.name)?;
+        if !self.properties.is_empty() {
+            let properties: Vec<String> = self
+                .properties
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            write!(f, "[{}]", properties.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// The blocks of a build, indexed by `[x, y, z]`. Positions without a block are air.
+struct Build {
+    size: [usize; 3],
+    palette: Vec<BlockState>,
+    indices: HashMap<BlockState, usize>,
+    blocks: HashMap<[usize; 3], usize>,
+}
+
+impl Build {
+    fn new() -> Self {
+        let air = BlockState::new("air");
+        Build {
+            size: [0; 3],
+            palette: vec![air.clone()],
+            indices: vec![(air, 0)].into_iter().collect(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    fn set(&mut self, position: [usize; 3], state: BlockState) {
+        let palette = &mut self.palette;
+        let index = *self.indices.entry(state).or_insert_with_key(|state| {
+            palette.push(state.clone());
+            palette.len() - 1
+        });
+        for (size, coordinate) in self.size.iter_mut().zip(position.iter()) {
+            *size = (*size).max(coordinate + 1);
+        }
+        self.blocks.insert(position, index);
+    }
+
+    /// Places the note blocks of a tick on one side of the redstone line.
+    fn branch(&mut self, x: usize, line: usize, south: bool, notes: &[NoteBlock]) {
+        let z = |distance| {
+            if south {
+                line + distance
+            } else {
+                line - distance
+            }
+        };
+        for (index, &(block, instrument, key)) in notes.iter().enumerate() {
+            let distance = index + 1 + index / BRANCH_SEGMENT;
+            if index > 0 && index % BRANCH_SEGMENT == 0 {
+                let facing = if south { "north" } else { "south" };
+                self.set([x, NOTE_BLOCK, z(distance - 1)], BlockState::new("stone"));
+                self.set(
+                    [x, REDSTONE, z(distance - 1)],
+                    BlockState::repeater(1, facing),
+                );
+            }
+            let z = z(distance);
+            self.set([x, FLOOR, z], BlockState::new("stone"));
+            self.set([x, INSTRUMENT, z], BlockState::new(block));
+            self.set(
+                [x, NOTE_BLOCK, z],
+                BlockState::new("note_block")
+                    .with("instrument", instrument)
+                    .with("note", key)
+                    .with("powered", false),
+            );
+            self.set(
+                [x, REDSTONE, z],
+                BlockState::redstone_wire(false, true, true, false),
+            );
+        }
+    }
+}
+
+/// Returns the distance of the last note block of a branch side with the given amount of notes.
+fn branch_length(notes: usize) -> usize {
+    match notes {
+        0 => 0,
+        notes => notes + (notes - 1) / BRANCH_SEGMENT,
+    }
+}
+
+/// Lays out the redstone line and the note blocks of a song.
+fn build(nbs: &Nbs) -> Build {
+    let tempo_map = nbs.tempo_map();
+    let columns: Vec<(Tick, Vec<NoteBlock>)> = nbs
+        .noteblocks
+        .columns()
+        .filter(|(tick, _)| *tick >= 0)
+        .map(|(tick, notes)| {
+            let notes = notes
+                .into_iter()
+                .filter_map(|(_, note)| {
+                    let name = vanilla::instrument_name(note.instrument)?;
+                    let key = vanilla::fold_octaves(vanilla::effective_key(note.key, note.pitch));
+                    Some((
+                        instrument_block(note.instrument),
+                        name,
+                        key.get() - vanilla::MIN_KEY.get(),
+                    ))
+                })
+                .collect();
+            (tick, notes)
+        })
+        .filter(|(_, notes): &(Tick, Vec<_>)| !notes.is_empty())
+        .collect();
+    // Notes alternate between the south and the north side, starting with the south.
+    let line = columns
+        .iter()
+        .map(|(_, notes)| branch_length(notes.len() / 2))
+        .max()
+        .unwrap_or(0);
+
+    let mut build = Build::new();
+    build.set([0, NOTE_BLOCK, line], BlockState::new("stone"));
+    build.set(
+        [0, REDSTONE, line],
+        BlockState::new("stone_button")
+            .with("face", "floor")
+            .with("facing", "east")
+            .with("powered", false),
+    );
+    let mut x = 0;
+    let mut tick = 0;
+    let mut redstone_ticks = 0u64;
+    let mut previous = 0;
+    for (index, (column, notes)) in columns.iter().enumerate() {
+        while tick < *column {
+            redstone_ticks += redstone_ticks_per_tick(tempo_map.ticks_per_second(tick)) as u64;
+            tick += 1;
+        }
+        let mut delay = redstone_ticks - previous;
+        previous = redstone_ticks;
+        while delay > 0 {
+            x += 1;
+            build.set([x, NOTE_BLOCK, line], BlockState::new("stone"));
+            build.set(
+                [x, REDSTONE, line],
+                BlockState::repeater(delay.min(MAX_REPEATER_DELAY), "west"),
+            );
+            delay -= delay.min(MAX_REPEATER_DELAY);
+        }
+        x += 1;
+        let south: Vec<_> = notes.iter().copied().step_by(2).collect();
+        let north: Vec<_> = notes.iter().copied().skip(1).step_by(2).collect();
+        build.set([x, NOTE_BLOCK, line], BlockState::new("stone"));
+        build.set(
+            [x, REDSTONE, line],
+            BlockState::redstone_wire(
+                index + 1 < columns.len(),
+                !north.is_empty(),
+                !south.is_empty(),
+                true,
+            ),
+        );
+        build.branch(x, line, true, &south);
+        build.branch(x, line, false, &north);
+    }
+    build.size[1] = REDSTONE + 1;
+    build
+}
+
+/// Compresses the NBT and writes it.
+fn write_nbt<W>(root: &Tag, name: &str, writer: &mut W) -> Result<(), NbsError>
+where
+    W: Write,
+{
+    let mut nbt = Vec::new();
+    root.write_root(name, &mut nbt)?;
+    archive::write_gzip(&nbt, writer)?;
+    Ok(())
+}
+
+/// Encode the song as a Sponge Schematic, which is usually saved as `.schem`.
+/// Fails if the build is longer than the 65535 blocks a schematic can hold.
+pub fn encode_schematic<W>(
+    nbs: &Nbs,
+    version: SchematicVersion,
+    writer: &mut W,
+) -> Result<(), NbsError>
+where
+    W: Write,
+{
+    let build = build(nbs);
+    let [width, height, length] = build.size;
+    if width > u16::MAX as usize || length > u16::MAX as usize {
+        return Err(NbsError::InvalidData(
+            "the song is too long for a schematic",
+        ));
+    }
+    let mut block_data = Vec::with_capacity(width * height * length);
+    for y in 0..height {
+        for z in 0..length {
+            for x in 0..width {
+                let mut index = build.blocks.get(&[x, y, z]).copied().unwrap_or(0);
+                // The palette indices are written as varints.
+                while index >= 0x80 {
+                    block_data.push((index & 0x7F) as u8 | 0x80);
+                    index >>= 7;
+                }
+                block_data.push(index as u8);
+            }
+        }
+    }
+    let palette = build
+        .palette
+        .iter()
+        .enumerate()
+        .map(|(index, state)| (state.to_string(), Tag::Int(index as i32)))
+        .collect();
+    let mut schematic = vec![
+        (
+            "Version".to_string(),
+            Tag::Int(match version {
+                SchematicVersion::V2 => 2,
+                SchematicVersion::V3 => 3,
+            }),
+        ),
+        ("DataVersion".to_string(), Tag::Int(DATA_VERSION)),
+        (
+            "Metadata".to_string(),
+            Tag::Compound(vec![(
+                "Name".to_string(),
+                Tag::String(nbs.header.song_name.clone()),
+            )]),
+        ),
+        ("Width".to_string(), Tag::Short(width as u16 as i16)),
+        ("Height".to_string(), Tag::Short(height as i16)),
+        ("Length".to_string(), Tag::Short(length as u16 as i16)),
+        ("Offset".to_string(), Tag::IntArray(vec![0, 0, 0])),
+    ];
+    match version {
+        SchematicVersion::V2 => {
+            schematic.extend(vec![
+                (
+                    "PaletteMax".to_string(),
+                    Tag::Int(build.palette.len() as i32),
+                ),
+                ("Palette".to_string(), Tag::Compound(palette)),
+                ("BlockData".to_string(), Tag::ByteArray(block_data)),
+                ("BlockEntities".to_string(), Tag::List(Vec::new())),
+            ]);
+            write_nbt(&Tag::Compound(schematic), "Schematic", writer)
+        }
+        SchematicVersion::V3 => {
+            // Version 3 groups the blocks and wraps the schematic in an unnamed root.
+            let blocks = Tag::Compound(vec![
+                ("Palette".to_string(), Tag::Compound(palette)),
+                ("Data".to_string(), Tag::ByteArray(block_data)),
+                ("BlockEntities".to_string(), Tag::List(Vec::new())),
+            ]);
+            schematic.push(("Blocks".to_string(), blocks));
+            let root = Tag::Compound(vec![("Schematic".to_string(), Tag::Compound(schematic))]);
+            write_nbt(&root, "", writer)
+        }
+    }
+}
+
+/// Encode the song as a vanilla structure, which is usually saved as `.nbt`.
+/// Positions without a block are left out, so they keep the blocks of the world when the structure is placed.
+pub fn encode_structure<W>(nbs: &Nbs, writer: &mut W) -> Result<(), NbsError>
+where
+    W: Write,
+{
+    let build = build(nbs);
+    let mut blocks: Vec<_> = build.blocks.iter().collect();
+    blocks.sort_by_key(|([x, y, z], _)| (*y, *z, *x));
+    let blocks = blocks
+        .into_iter()
+        .map(|(position, &index)| {
+            let position = position.iter().map(|&c| Tag::Int(c as i32)).collect();
+            Tag::Compound(vec![
+                ("pos".to_string(), Tag::List(position)),
+                ("state".to_string(), Tag::Int(index as i32)),
+            ])
+        })
+        .collect();
+    let root = Tag::Compound(vec![
+        ("DataVersion".to_string(), Tag::Int(DATA_VERSION)),
+        (
+            "size".to_string(),
+            Tag::List(build.size.iter().map(|&s| Tag::Int(s as i32)).collect()),
+        ),
+        (
+            "palette".to_string(),
+            Tag::List(build.palette.iter().map(BlockState::to_tag).collect()),
+        ),
+        ("blocks".to_string(), Tag::List(blocks)),
+        ("entities".to_string(), Tag::List(Vec::new())),
+    ]);
+    write_nbt(&root, "", writer)
+}
@@ -0,0 +1,93 @@
+//! A writer for the Named Binary Tag format used by Minecraft.
+
+use byteorder::{BigEndian, WriteBytesExt};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone)]
+pub(crate) enum Tag {
+    Short(i16),
+    Int(i32),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+}
+
+impl Tag {
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Short(_) => 2,
+            Tag::Int(_) => 3,
+            Tag::ByteArray(_) => 7,
+            Tag::String(_) => 8,
+            Tag::List(_) => 9,
+            Tag::Compound(_) => 10,
+            Tag::IntArray(_) => 11,
+        }
+    }
+
+    /// Writes the tag as the root of a NBT file.
+    pub(crate) fn write_root<W>(&self, name: &str, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_u8(self.id())?;
+        write_string(name, writer)?;
+        self.write_payload(writer)
+    }
+
+    fn write_payload<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        match self {
+            Tag::Short(value) => writer.write_i16::<BigEndian>(*value),
+            Tag::Int(value) => writer.write_i32::<BigEndian>(*value),
+            Tag::ByteArray(bytes) => {
+                writer.write_i32::<BigEndian>(bytes.len() as i32)?;
+                writer.write_all(bytes)
+            }
+            Tag::String(value) => write_string(value, writer),
+            Tag::List(tags) => {
+                // An empty list has the element type of the end tag.
+                writer.write_u8(tags.first().map_or(0, Tag::id))?;
+                writer.write_i32::<BigEndian>(tags.len() as i32)?;
+                for tag in tags {
+                    tag.write_payload(writer)?;
+                }
+                Ok(())
+            }
+            Tag::Compound(entries) => {
+                for (name, tag) in entries {
+                    writer.write_u8(tag.id())?;
+                    write_string(name, writer)?;
+                    tag.write_payload(writer)?;
+                }
+                writer.write_u8(0)
+            }
+            Tag::IntArray(values) => {
+                writer.write_i32::<BigEndian>(values.len() as i32)?;
+                for value in values {
+                    writer.write_i32::<BigEndian>(*value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Writes a string with its length as a prefix.
+/// Only block ids and song names are written, so the difference between UTF-8 and Java's modified UTF-8 is ignored.
+fn write_string<W>(value: &str, writer: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut end = value.len().min(u16::MAX as usize);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    let bytes = &value.as_bytes()[..end];
+    writer.write_u16::<BigEndian>(bytes.len() as u16)?;
+    writer.write_all(bytes)
+}
@@ -0,0 +1,154 @@
+//! Songs with a variable tempo.
+//!
+//! Open Note Block Studio changes the tempo mid-song with notes of a custom instrument named "Tempo Changer".
+//! The fine pitch of such a note is the new tempo in beats per minute, where one beat lasts 4 ticks.
+//! A `TempoMap` collects these changes, so ticks can be converted to time and back.
+//!
+//! ## Example: Listing the tempo changes of a song
+//!
+//! ```rust
+//! use nbs::Nbs;
+//! use std::fs::File;
+//!
+//! let nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let tempo_map = nbs.tempo_map();
+//! for (tick, ticks_per_second) in tempo_map.changes() {
+//!     println!("{} ticks per second from tick {}", ticks_per_second, tick);
+//! }
+//! println!("The song lasts {:?}", tempo_map.tick_to_time(nbs.song_ticks()));
+//! ```
+
+use crate::{Nbs, Tick};
+use std::time::Duration;
+
+/// The lowest supported tempo, lower tempos (like a tempo of 0) are raised to it.
+pub const MIN_TICKS_PER_SECOND: f64 = 0.01;
+
+/// Converts a tempo changer pitch (beats per minute) to ticks per second.
+pub fn bpm_to_ticks_per_second(bpm: f64) -> f64 {
+    bpm / 15.0
+}
+
+/// Converts ticks per second to a tempo changer pitch (beats per minute).
+pub fn ticks_per_second_to_bpm(ticks_per_second: f64) -> f64 {
+    ticks_per_second * 15.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TempoChange {
+    tick: Tick,
+    ticks_per_second: f64,
+    /// The time in seconds at which this change happens.
+    seconds: f64,
+}
+
+/// The tempo of a song over time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    /// Sorted by tick, the first change is always at tick 0.
+    changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    /// Creates a map with a constant tempo.
+    pub fn new(ticks_per_second: f64) -> Self {
+        TempoMap {
+            changes: vec![TempoChange {
+                tick: 0,
+                ticks_per_second: ticks_per_second.max(MIN_TICKS_PER_SECOND),
+                seconds: 0.0,
+            }],
+        }
+    }
+
+    /// Creates the tempo map of a song from its tempo and its tempo changer notes.
+    /// When multiple tempo changers are at the same tick, the one in the last layer wins.
+    pub fn from_nbs(nbs: &Nbs) -> Self {
+        let mut tempo_map = TempoMap::new(nbs.header.song_tempo as f64 / 100.0);
+        if let Some(tempo_changer) = nbs.custom_instruments.tempo_changer() {
+            for (tick, _, note) in nbs.noteblocks.events() {
+                match note.pitch {
+                    // Like in Note Block Studio a negative pitch is played at the tempo of its magnitude.
+                    Some(pitch) if note.instrument == tempo_changer && tick >= 0 && pitch != 0 => {
+                        let bpm = pitch.unsigned_abs() as f64;
+                        tempo_map.insert(tick, bpm_to_ticks_per_second(bpm));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        tempo_map
+    }
+
+    /// Changes the tempo from a tick onwards, until the next change.
+    pub fn insert(&mut self, tick: Tick, ticks_per_second: f64) {
+        let tick = tick.max(0);
+        let ticks_per_second = ticks_per_second.max(MIN_TICKS_PER_SECOND);
+        let index = self.changes.partition_point(|change| change.tick < tick);
+        match self.changes.get_mut(index) {
+            Some(change) if change.tick == tick => change.ticks_per_second = ticks_per_second,
+            _ => self.changes.insert(
+                index,
+                TempoChange {
+                    tick,
+                    ticks_per_second,
+                    seconds: 0.0,
+                },
+            ),
+        }
+        // The times of all following changes move.
+        for index in index.max(1)..self.changes.len() {
+            let previous = self.changes[index - 1];
+            self.changes[index].seconds = previous.seconds
+                + (self.changes[index].tick - previous.tick) as f64 / previous.ticks_per_second;
+        }
+    }
+
+    /// Returns every change as `(tick, ticks_per_second)`, starting with the tempo at tick 0.
+    pub fn changes(&self) -> impl Iterator<Item = (Tick, f64)> + '_ {
+        self.changes
+            .iter()
+            .map(|change| (change.tick, change.ticks_per_second))
+    }
+
+    /// Returns true if the tempo never changes.
+    pub fn is_constant(&self) -> bool {
+        self.changes.len() == 1
+    }
+
+    /// Returns the tempo at a tick in ticks per second.
+    pub fn ticks_per_second(&self, tick: Tick) -> f64 {
+        self.change_at_tick(tick).ticks_per_second
+    }
+
+    /// Returns the time at which a tick is played.
+    pub fn tick_to_time(&self, tick: Tick) -> Duration {
+        Duration::from_secs_f64(self.tick_to_seconds(tick as f64))
+    }
+
+    /// Returns the time at which a fractional tick is reached, like the end of the last tick of a song.
+    pub fn tick_to_seconds(&self, tick: f64) -> f64 {
+        let tick = tick.max(0.0);
+        let change = self.change_at_tick(tick.floor().min(Tick::MAX as f64) as Tick);
+        change.seconds + (tick - change.tick as f64) / change.ticks_per_second
+    }
+
+    /// Returns the tick that is played at a time, including the fraction of the tick that has passed.
+    pub fn time_to_tick(&self, time: Duration) -> f64 {
+        let seconds = time.as_secs_f64();
+        let index = self
+            .changes
+            .partition_point(|change| change.seconds <= seconds)
+            .max(1);
+        let change = &self.changes[index - 1];
+        change.tick as f64 + (seconds - change.seconds) * change.ticks_per_second
+    }
+
+    fn change_at_tick(&self, tick: Tick) -> &TempoChange {
+        let index = self
+            .changes
+            .partition_point(|change| change.tick <= tick)
+            .max(1);
+        &self.changes[index - 1]
+    }
+}
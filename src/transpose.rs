@@ -0,0 +1,191 @@
+//! Transposing songs by semitones or cents.
+//!
+//! Whole semitones change the key of a note, the remaining cents are carried into the fine pitch.
+//! Formats before version 4 have no fine pitch, so there the transposition is rounded to whole semitones.
+//!
+//! ## Example: Moving the bass an octave up
+//!
+//! ```rust
+//! use nbs::{
+//!     noteblocks::instrument,
+//!     transpose::{OutOfRange, Transpose},
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let mut nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let transpose = Transpose {
+//!     instrument: Some(instrument::DOUBLE_BASS),
+//!     ticks: Some(0..64),
+//!     out_of_range: OutOfRange::Fold,
+//!     ..Transpose::semitones(12)
+//! };
+//! let report = nbs.transpose(&transpose).unwrap();
+//! println!("{} notes were transposed", report.transposed);
+//! ```
+
+use crate::{
+    noteblocks::{instrument::Instrument, layer::Layer, note::Note, value::Key},
+    NbsError, Tick,
+};
+use std::ops::Range;
+
+/// What happens to notes whose key would leave 0-87.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfRange {
+    /// The key is clamped to 0 or 87.
+    Clamp,
+    /// The key is moved by whole octaves until it is within range.
+    Fold,
+    /// The note is removed.
+    Drop,
+    /// Nothing is changed and `NbsError::KeyOutOfRange` is returned.
+    Error,
+}
+
+/// Describes a transposition and the notes it applies to.
+/// Notes have to match every scope that is set, `None` matches every note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transpose {
+    /// The amount of cents to transpose by, 100 cents are a semitone.
+    pub cents: i32,
+    /// The indices of the layers to transpose.
+    pub layers: Option<Vec<usize>>,
+    /// The instrument whose notes are transposed.
+    pub instrument: Option<Instrument>,
+    /// The ticks whose notes are transposed.
+    pub ticks: Option<Range<Tick>>,
+    /// What happens to notes whose key would leave 0-87, `Error` by default.
+    pub out_of_range: OutOfRange,
+}
+
+impl Transpose {
+    /// Transposes every note by whole semitones.
+    pub fn semitones(semitones: i32) -> Self {
+        Transpose::cents(semitones.saturating_mul(100))
+    }
+
+    /// Transposes every note by cents.
+    pub fn cents(cents: i32) -> Self {
+        Transpose {
+            cents,
+            layers: None,
+            instrument: None,
+            ticks: None,
+            out_of_range: OutOfRange::Error,
+        }
+    }
+
+    /// Returns true if the note is within scope.
+    /// `layer` is `None` when a single layer is transposed, which ignores the layer scope.
+    fn includes(&self, layer: Option<usize>, tick: Tick, note: &Note) -> bool {
+        let layers = self.layers.as_ref().zip(layer);
+        layers.iter().all(|(layers, layer)| layers.contains(layer))
+            && self.instrument.iter().all(|&i| i == note.instrument)
+            && self.ticks.iter().all(|t| t.contains(&tick))
+    }
+
+    /// Returns the new key, which may be out of range, and the new fine pitch of a note.
+    fn shift(&self, note: &Note) -> (i32, Option<i16>) {
+        let key = note.key.get() as i32;
+        match note.pitch {
+            Some(pitch) if self.cents % 100 != 0 => {
+                let pitch = pitch as i32 + self.cents % 100;
+                (
+                    key + self.cents / 100 + pitch / 100,
+                    Some((pitch % 100) as i16),
+                )
+            }
+            Some(pitch) => (key + self.cents / 100, Some(pitch)),
+            None => (key + (self.cents as f64 / 100.0).round() as i32, None),
+        }
+    }
+}
+
+impl Default for Transpose {
+    fn default() -> Self {
+        Transpose::cents(0)
+    }
+}
+
+/// Describes what a transposition changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransposeReport {
+    /// Amount of notes that were transposed, including clamped and folded notes.
+    pub transposed: usize,
+    /// Amount of notes without a fine pitch, whose transposition was rounded to whole semitones.
+    pub rounded: usize,
+    /// Amount of notes whose key was clamped to 0-87.
+    pub clamped: usize,
+    /// Amount of notes whose key was moved by whole octaves into 0-87.
+    pub folded: usize,
+    /// Amount of notes that were removed, because their key left 0-87.
+    pub dropped: usize,
+}
+
+/// Moves a key by whole octaves until it is within 0-87.
+fn fold_octaves(key: i32) -> Key {
+    let max = Key::MAX.get() as i32;
+    if key < 0 {
+        Key::from_raw((key + 12 * ((11 - key) / 12)) as u8)
+    } else if key > max {
+        Key::from_raw((key - 12 * ((key - max + 11) / 12)) as u8)
+    } else {
+        Key::from_raw(key as u8)
+    }
+}
+
+/// Transposes the notes of the layers in scope.
+/// Without `layer_scope` the layer scope is ignored, `skip` is an instrument that is never transposed.
+pub(crate) fn transpose(
+    layers: &mut [Layer],
+    layer_scope: bool,
+    transpose: &Transpose,
+    skip: Option<Instrument>,
+) -> Result<TransposeReport, NbsError> {
+    let index = |i: usize| if layer_scope { Some(i) } else { None };
+    let includes = |layer: Option<usize>, tick: Tick, note: &Note| {
+        Some(note.instrument) != skip && transpose.includes(layer, tick, note)
+    };
+    let in_range = |key: i32| (0..=Key::MAX.get() as i32).contains(&key);
+    if transpose.out_of_range == OutOfRange::Error {
+        for (i, layer) in layers.iter().enumerate() {
+            for (&tick, note) in &layer.notes {
+                let (key, _) = transpose.shift(note);
+                if includes(index(i), tick, note) && !in_range(key) {
+                    return Err(NbsError::KeyOutOfRange { tick, key });
+                }
+            }
+        }
+    }
+    let mut report = TransposeReport::default();
+    for (i, layer) in layers.iter_mut().enumerate() {
+        layer.notes.retain(|&tick, note| {
+            if !includes(index(i), tick, note) {
+                return true;
+            }
+            let (key, pitch) = transpose.shift(note);
+            if !in_range(key) {
+                match transpose.out_of_range {
+                    OutOfRange::Drop => {
+                        report.dropped += 1;
+                        return false;
+                    }
+                    OutOfRange::Fold => report.folded += 1,
+                    OutOfRange::Clamp | OutOfRange::Error => report.clamped += 1,
+                }
+            }
+            if pitch.is_none() && transpose.cents % 100 != 0 {
+                report.rounded += 1;
+            }
+            note.key = match transpose.out_of_range {
+                OutOfRange::Fold => fold_octaves(key),
+                _ => Key::saturating(key),
+            };
+            note.pitch = pitch;
+            report.transposed += 1;
+            true
+        });
+    }
+    Ok(report)
+}
@@ -0,0 +1,515 @@
+//! Checking songs for problems that break Note Block Studio or players.
+//!
+//! ## Example: Fixing a song before saving it
+//!
+//! ```rust
+//! use nbs::{noteblocks::value::Volume, validation::Severity, Nbs};
+//! use std::fs::File;
+//!
+//! let mut nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! nbs.noteblocks.layers[0].volume = Volume::from_raw(120);
+//! for issue in nbs.validate() {
+//!     println!("{}", issue);
+//! }
+//! // Corrects the volume, the remaining issues have to be fixed by hand.
+//! let remaining = nbs.fix();
+//! assert!(remaining.iter().all(|issue| issue.severity != Severity::Error));
+//! ```
+
+use crate::{
+    noteblocks::{
+        instrument::Instrument,
+        value::{Key, Panning, Velocity, Volume},
+    },
+    Nbs, Tick,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    fmt,
+};
+
+/// How severe an issue is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The song can be encoded and played, but some values are outside of the range Note Block Studio uses.
+    Warning,
+    /// The song can not be encoded, or breaks Note Block Studio or players.
+    Error,
+}
+
+/// Where an issue was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    Header,
+    Layer(usize),
+    Note {
+        tick: Tick,
+        layer: usize,
+    },
+    /// The index of the custom instrument.
+    CustomInstrument(usize),
+}
+
+/// What is wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IssueKind {
+    /// The format of the song can not be encoded.
+    UnsupportedFormat,
+    /// A field that the format of the song requires is `None`.
+    MissingField(&'static str),
+    /// `Header::version_number` differs from the version of the format.
+    VersionMismatch(i8),
+    /// `Header::layer_count` differs from the amount of layers.
+    LayerCountMismatch { layer_count: i16, layers: usize },
+    /// The song length stored in the header does not match the notes.
+    StaleSongLength { song_length: Tick, expected: Tick },
+    /// The tempo is zero or negative.
+    InvalidTempo(i16),
+    /// The note is placed before the first tick and can not be encoded.
+    NegativeTick,
+    /// The note is more than `i16::MAX` ticks after the previous tick with notes, which the 16-bit jumps of the format can not express.
+    TickOutOfRange,
+    /// A tick of the header does not fit into its 16-bit field, like the song length of a song longer than `i16::MAX` ticks.
+    HeaderTickOutOfRange { field: &'static str, tick: Tick },
+    /// The key is above 87.
+    KeyOutOfRange(Key),
+    /// The velocity is above 100.
+    VelocityOutOfRange(Velocity),
+    /// The panning is above 200.
+    PanningOutOfRange(Panning),
+    /// The layer volume is above 100.
+    VolumeOutOfRange(Volume),
+    /// The layer stereo position is above 200.
+    StereoOutOfRange(Panning),
+    /// The instrument is neither a vanilla instrument of the format nor a custom instrument of the song.
+    UnknownInstrument(Instrument),
+    /// Custom instruments are encoded in order, so the id of a custom instrument has to match its position.
+    CustomInstrumentOrder {
+        instrument: Instrument,
+        expected: Instrument,
+    },
+}
+
+impl IssueKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            IssueKind::StaleSongLength { .. }
+            | IssueKind::VelocityOutOfRange(_)
+            | IssueKind::PanningOutOfRange(_)
+            | IssueKind::VolumeOutOfRange(_)
+            | IssueKind::StereoOutOfRange(_) => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Returns true if `Nbs::fix` corrects this issue.
+    pub fn is_fixable(&self) -> bool {
+        !matches!(
+            self,
+            IssueKind::UnsupportedFormat
+                | IssueKind::InvalidTempo(_)
+                | IssueKind::NegativeTick
+                | IssueKind::TickOutOfRange
+                | IssueKind::HeaderTickOutOfRange { .. }
+                | IssueKind::UnknownInstrument(_)
+        )
+    }
+}
+
+/// A problem found by `Nbs::validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Issue {
+    pub severity: Severity,
+    pub location: Location,
+    pub kind: IssueKind,
+}
+
+impl Issue {
+    fn new(location: Location, kind: IssueKind) -> Self {
+        Issue {
+            severity: kind.severity(),
+            location,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Header => write!(f, "header"),
+            Location::Layer(layer) => write!(f, "layer {}", layer),
+            Location::Note { tick, layer } => write!(f, "note at tick {} in layer {}", tick, layer),
+            Location::CustomInstrument(index) => write!(f, "custom instrument {}", index),
+        }
+    }
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::UnsupportedFormat => write!(f, "the format is not supported"),
+            IssueKind::MissingField(field) => write!(f, "`{}` is missing", field),
+            IssueKind::VersionMismatch(version_number) => write!(
+                f,
+                "the version number {} differs from the format",
+                version_number
+            ),
+            IssueKind::LayerCountMismatch {
+                layer_count,
+                layers,
+            } => write!(
+                f,
+                "the layer count is {}, but there are {} layers",
+                layer_count, layers
+            ),
+            IssueKind::StaleSongLength {
+                song_length,
+                expected,
+            } => write!(
+                f,
+                "the song length is {}, but should be {}",
+                song_length, expected
+            ),
+            IssueKind::InvalidTempo(tempo) => write!(f, "the tempo {} is not positive", tempo),
+            IssueKind::NegativeTick => write!(f, "the tick is negative"),
+            IssueKind::TickOutOfRange => write!(
+                f,
+                "the tick is more than {} ticks after the previous note",
+                i16::MAX
+            ),
+            IssueKind::HeaderTickOutOfRange { field, tick } => {
+                write!(f, "the {} {} is above {}", field, tick, i16::MAX)
+            }
+            IssueKind::KeyOutOfRange(key) => write!(f, "the key {} is above 87", key),
+            IssueKind::VelocityOutOfRange(velocity) => {
+                write!(f, "the velocity {} is above 100", velocity)
+            }
+            IssueKind::PanningOutOfRange(panning) => {
+                write!(f, "the panning {} is above 200", panning)
+            }
+            IssueKind::VolumeOutOfRange(volume) => write!(f, "the volume {} is above 100", volume),
+            IssueKind::StereoOutOfRange(stereo) => {
+                write!(f, "the stereo position {} is above 200", stereo)
+            }
+            IssueKind::UnknownInstrument(instrument) => {
+                write!(f, "the instrument {:?} does not exist", instrument)
+            }
+            IssueKind::CustomInstrumentOrder {
+                instrument,
+                expected,
+            } => write!(
+                f,
+                "the instrument is {:?}, but its position requires {:?}",
+                instrument, expected
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in the {}: {}",
+            self.severity, self.location, self.kind
+        )
+    }
+}
+
+/// Returns the song length the header should store for the format of the song.
+pub(crate) fn expected_song_length(nbs: &Nbs) -> Option<Tick> {
+    let version = nbs.format().version();
+    if version >= 5 {
+        // Since version 5 the song length is the amount of ticks, not the last tick.
+        let has_notes = nbs.noteblocks.layers.iter().any(|l| !l.notes.is_empty());
+        Some(if has_notes {
+            nbs.noteblocks.calculate_length().saturating_add(1)
+        } else {
+            0
+        })
+    } else if version >= 3 || version == 0 {
+        Some(nbs.noteblocks.calculate_length())
+    } else {
+        None
+    }
+}
+
+/// Returns the id every custom instrument should have, by its position.
+fn expected_custom_ids(nbs: &Nbs) -> Vec<(Instrument, Instrument)> {
+    let vannila_instrument_count = nbs.header.vannila_instrument_count().unwrap_or(16);
+    nbs.custom_instruments
+        .iter()
+        .enumerate()
+        .map(|(index, info)| {
+            let expected = Instrument::Custom(
+                vannila_instrument_count.saturating_add(index.min(u8::MAX as usize) as u8),
+            );
+            (info.instrument, expected)
+        })
+        .collect()
+}
+
+pub(crate) fn validate(nbs: &Nbs) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let format = nbs.format();
+    let version = format.version();
+    let header = &nbs.header;
+    if !format.is_supported() {
+        issues.push(Issue::new(Location::Header, IssueKind::UnsupportedFormat));
+    }
+    if format.is_new() {
+        let fields = [
+            ("version_number", header.version_number.is_none()),
+            (
+                "vannila_instrument_count",
+                header.vannila_instrument_count.is_none(),
+            ),
+            ("song_length", version >= 3 && header.song_length.is_none()),
+            ("is_loop", header.is_loop.is_none()),
+            ("max_loop_count", header.max_loop_count.is_none()),
+            ("loop_start_tick", header.loop_start_tick.is_none()),
+        ];
+        for (field, missing) in fields.iter() {
+            if *missing {
+                issues.push(Issue::new(Location::Header, IssueKind::MissingField(field)));
+            }
+        }
+        match header.version_number {
+            Some(version_number) if version_number != version => issues.push(Issue::new(
+                Location::Header,
+                IssueKind::VersionMismatch(version_number),
+            )),
+            _ => {}
+        }
+    }
+    if header.layer_count as isize != nbs.noteblocks.layers.len() as isize {
+        issues.push(Issue::new(
+            Location::Header,
+            IssueKind::LayerCountMismatch {
+                layer_count: header.layer_count,
+                layers: nbs.noteblocks.layers.len(),
+            },
+        ));
+    }
+    let song_length = if version == 0 {
+        Some(header.old_song_length)
+    } else {
+        header.song_length
+    };
+    if let (Some(song_length), Some(expected)) = (song_length, expected_song_length(nbs)) {
+        if expected > i16::MAX as Tick {
+            let field = if version == 0 {
+                "old_song_length"
+            } else {
+                "song_length"
+            };
+            issues.push(Issue::new(
+                Location::Header,
+                IssueKind::HeaderTickOutOfRange {
+                    field,
+                    tick: expected,
+                },
+            ));
+        } else if song_length != expected {
+            issues.push(Issue::new(
+                Location::Header,
+                IssueKind::StaleSongLength {
+                    song_length,
+                    expected,
+                },
+            ));
+        }
+    }
+    match header.loop_start_tick {
+        Some(tick) if i16::try_from(tick).is_err() => issues.push(Issue::new(
+            Location::Header,
+            IssueKind::HeaderTickOutOfRange {
+                field: "loop_start_tick",
+                tick,
+            },
+        )),
+        _ => {}
+    }
+    if header.song_tempo <= 0 {
+        issues.push(Issue::new(
+            Location::Header,
+            IssueKind::InvalidTempo(header.song_tempo),
+        ));
+    }
+
+    // Ticks that are too far after the previous tick with notes to be reached by a 16-bit jump.
+    let mut previous = -1;
+    let mut unreachable = HashSet::new();
+    for (tick, _) in nbs.noteblocks.columns().filter(|(tick, _)| *tick >= 0) {
+        if tick - previous > i16::MAX as Tick {
+            unreachable.insert(tick);
+        }
+        previous = tick;
+    }
+
+    let vannila_instrument_count = header.vannila_instrument_count().unwrap_or(16);
+    for (layer_index, layer) in nbs.noteblocks.layers.iter().enumerate() {
+        let location = Location::Layer(layer_index);
+        let fields = [
+            ("locked", version >= 4 && layer.locked.is_none()),
+            ("solo", version >= 5 && layer.solo.is_none()),
+            ("stereo", version >= 2 && layer.stereo.is_none()),
+        ];
+        for (field, missing) in fields.iter() {
+            if *missing {
+                issues.push(Issue::new(location, IssueKind::MissingField(field)));
+            }
+        }
+        if !layer.volume.is_valid() {
+            issues.push(Issue::new(
+                location,
+                IssueKind::VolumeOutOfRange(layer.volume),
+            ));
+        }
+        match layer.stereo {
+            Some(stereo) if !stereo.is_valid() => {
+                issues.push(Issue::new(location, IssueKind::StereoOutOfRange(stereo)));
+            }
+            _ => {}
+        }
+        for (&tick, note) in &layer.notes {
+            let location = Location::Note {
+                tick,
+                layer: layer_index,
+            };
+            if tick < 0 {
+                issues.push(Issue::new(location, IssueKind::NegativeTick));
+            }
+            if unreachable.contains(&tick) {
+                issues.push(Issue::new(location, IssueKind::TickOutOfRange));
+            }
+            if version >= 4 {
+                let fields = [
+                    ("velocity", note.velocity.is_none()),
+                    ("panning", note.panning.is_none()),
+                    ("pitch", note.pitch.is_none()),
+                ];
+                for (field, missing) in fields.iter() {
+                    if *missing {
+                        issues.push(Issue::new(location, IssueKind::MissingField(field)));
+                    }
+                }
+            }
+            if !note.key.is_valid() {
+                issues.push(Issue::new(location, IssueKind::KeyOutOfRange(note.key)));
+            }
+            match note.velocity {
+                Some(velocity) if !velocity.is_valid() => {
+                    issues.push(Issue::new(
+                        location,
+                        IssueKind::VelocityOutOfRange(velocity),
+                    ));
+                }
+                _ => {}
+            }
+            match note.panning {
+                Some(panning) if !panning.is_valid() => {
+                    issues.push(Issue::new(location, IssueKind::PanningOutOfRange(panning)));
+                }
+                _ => {}
+            }
+            let known = match note.instrument {
+                Instrument::Vanilla(id) => (0..vannila_instrument_count).contains(&id),
+                Instrument::Custom(_) => nbs.custom_instruments.get(note.instrument).is_some(),
+            };
+            if !known {
+                issues.push(Issue::new(
+                    location,
+                    IssueKind::UnknownInstrument(note.instrument),
+                ));
+            }
+        }
+    }
+
+    for (index, (instrument, expected)) in expected_custom_ids(nbs).into_iter().enumerate() {
+        if instrument != expected {
+            issues.push(Issue::new(
+                Location::CustomInstrument(index),
+                IssueKind::CustomInstrumentOrder {
+                    instrument,
+                    expected,
+                },
+            ));
+        }
+    }
+    issues
+}
+
+pub(crate) fn fix(nbs: &mut Nbs) -> Vec<Issue> {
+    let format = nbs.format();
+    let version = format.version();
+    if format.is_new() {
+        let header = &mut nbs.header;
+        header.vannila_instrument_count.get_or_insert(16);
+        header.is_loop.get_or_insert(false);
+        header.max_loop_count.get_or_insert(0);
+        header.loop_start_tick.get_or_insert(0);
+    }
+
+    // Renumber custom instruments by their position, moving their notes along.
+    let ids: HashMap<Instrument, Instrument> = expected_custom_ids(nbs)
+        .into_iter()
+        .filter(|(instrument, expected)| instrument != expected)
+        .collect();
+    if !ids.is_empty() {
+        for (_, _, note) in nbs.noteblocks.events_mut() {
+            if let Some(&expected) = ids.get(&note.instrument) {
+                note.instrument = expected;
+            }
+        }
+        let expected: Vec<_> = expected_custom_ids(nbs)
+            .into_iter()
+            .map(|(_, expected)| expected)
+            .collect();
+        nbs.custom_instruments.renumber(&expected);
+    }
+
+    for layer in &mut nbs.noteblocks.layers {
+        if version >= 4 {
+            layer.locked.get_or_insert(false);
+        }
+        if version >= 5 {
+            layer.solo.get_or_insert(false);
+        }
+        if version >= 2 {
+            layer.stereo.get_or_insert(Panning::CENTER);
+        }
+        layer.volume = Volume::saturating(layer.volume.get() as i32);
+        if let Some(stereo) = &mut layer.stereo {
+            *stereo = Panning::saturating(stereo.get() as i32);
+        }
+        for note in layer.notes.values_mut() {
+            if version >= 4 {
+                note.velocity.get_or_insert_with(Velocity::default);
+                note.panning.get_or_insert(Panning::CENTER);
+                note.pitch.get_or_insert(0);
+            }
+            note.key = Key::saturating(note.key.get() as i32);
+            if let Some(velocity) = &mut note.velocity {
+                *velocity = Velocity::saturating(velocity.get() as i32);
+            }
+            if let Some(panning) = &mut note.panning {
+                *panning = Panning::saturating(panning.get() as i32);
+            }
+        }
+    }
+
+    nbs.sync_header();
+    validate(nbs)
+}
@@ -0,0 +1,248 @@
+//! Checking whether a song can be built with vanilla Minecraft note blocks, and folding it into their range.
+//!
+//! A note block covers two octaves, keys 33-57 (F#3 to F#5 on the piano).
+//! Custom instruments and fine pitch do not exist in vanilla Minecraft.
+//!
+//! ## Example: Making a song playable in-game
+//!
+//! ```rust
+//! use nbs::{
+//!     vanilla::{self, FoldStrategy},
+//!     Nbs,
+//! };
+//! use std::fs::File;
+//!
+//! let mut nbs = Nbs::decode(&mut File::open("tests/1.nbs").unwrap()).unwrap();
+//! let report = vanilla::analyze(&nbs);
+//! for note in &report.notes {
+//!     println!("{:?}", note);
+//! }
+//! let report = vanilla::fold(&mut nbs, FoldStrategy::PreferSibling);
+//! println!("{} notes were changed", report.changes.len());
+//! assert!(vanilla::analyze(&nbs)
+//!     .notes
+//!     .iter()
+//!     .all(|note| note.problem == vanilla::Problem::CustomInstrument));
+//! ```
+
+use crate::{
+    noteblocks::{
+        instrument::{self, Instrument},
+        value::Key,
+    },
+    Nbs, Tick,
+};
+
+/// The lowest key a vanilla note block can play.
+pub const MIN_KEY: Key = Key::from_raw(33);
+/// The highest key a vanilla note block can play.
+pub const MAX_KEY: Key = Key::from_raw(57);
+
+/// Instruments that sound alike, with the octave they are played in relative to the piano, from low to high.
+const SIBLINGS: [&[(Instrument, i8)]; 2] = [
+    &[(instrument::DOUBLE_BASS, -2), (instrument::GUITAR, -1)],
+    &[(instrument::IRON_XYLOPHONE, 0), (instrument::XYLOPHONE, 2)],
+];
+
+/// Returns the instruments that sound like the given one, but in a different octave.
+/// Each sibling comes with the amount of octaves it is higher (or lower, if negative) than the instrument.
+pub fn siblings(instrument: Instrument) -> Vec<(Instrument, i8)> {
+    SIBLINGS
+        .iter()
+        .find_map(|family| {
+            let octave = family.iter().find(|(i, _)| *i == instrument)?.1;
+            Some(
+                family
+                    .iter()
+                    .filter(|(i, _)| *i != instrument)
+                    .map(|&(i, o)| (i, o - octave))
+                    .collect(),
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the name Minecraft uses for a vanilla instrument, as in `block.note_block.<name>` sounds and the `instrument` property of note blocks.
+pub fn instrument_name(instrument: Instrument) -> Option<&'static str> {
+    Some(match instrument {
+        instrument::PIANO => "harp",
+        instrument::DOUBLE_BASS => "bass",
+        instrument::BASS_DRUM => "basedrum",
+        instrument::SNARE_DRUM => "snare",
+        instrument::CLICK => "hat",
+        instrument::GUITAR => "guitar",
+        instrument::FLUTE => "flute",
+        instrument::BELL => "bell",
+        instrument::CHIME => "chime",
+        instrument::XYLOPHONE => "xylophone",
+        instrument::IRON_XYLOPHONE => "iron_xylophone",
+        instrument::COW_BELL => "cow_bell",
+        instrument::DIDGERIDOO => "didgeridoo",
+        instrument::BIT => "bit",
+        instrument::BANJO => "banjo",
+        instrument::PLING => "pling",
+        _ => return None,
+    })
+}
+
+/// Why a note can not be played by a vanilla note block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Problem {
+    /// The key, including whole semitones of the fine pitch, is outside of 33-57.
+    KeyOutOfRange(i8),
+    /// The note uses a custom instrument.
+    CustomInstrument,
+    /// The fine pitch is not a multiple of 100 cents.
+    FinePitch(i16),
+}
+
+/// A note that can not be played by a vanilla note block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UnplayableNote {
+    pub tick: Tick,
+    pub layer: usize,
+    pub problem: Problem,
+}
+
+/// Every problem found by `analyze`, ordered by tick and then by layer.
+/// A note with multiple problems is listed once for each of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayabilityReport {
+    pub notes: Vec<UnplayableNote>,
+}
+
+impl PlayabilityReport {
+    /// Returns true if every note can be played by a vanilla note block.
+    pub fn is_playable(&self) -> bool {
+        self.notes.is_empty()
+    }
+}
+
+/// Returns the key a note sounds at, with the whole semitones of its fine pitch added.
+pub(crate) fn effective_key(key: Key, pitch: Option<i16>) -> i32 {
+    key.get() as i32 + (pitch.unwrap_or(0) as f32 / 100.0).round() as i32
+}
+
+/// Moves a key by whole octaves until it is within the range of a note block.
+pub(crate) fn fold_octaves(mut key: i32) -> Key {
+    while key < MIN_KEY.get() as i32 {
+        key += 12;
+    }
+    while key > MAX_KEY.get() as i32 {
+        key -= 12;
+    }
+    Key::from_raw(key as u8)
+}
+
+/// Finds every note that can not be played by a vanilla note block.
+pub fn analyze(nbs: &Nbs) -> PlayabilityReport {
+    let mut report = PlayabilityReport::default();
+    for (tick, layer, note) in nbs.noteblocks.events() {
+        let mut push = |problem| {
+            report.notes.push(UnplayableNote {
+                tick,
+                layer,
+                problem,
+            })
+        };
+        let key = effective_key(note.key, note.pitch);
+        if key < MIN_KEY.get() as i32 || key > MAX_KEY.get() as i32 {
+            push(Problem::KeyOutOfRange(
+                key.clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+            ));
+        }
+        if note.instrument.is_custom() {
+            push(Problem::CustomInstrument);
+        }
+        match note.pitch {
+            Some(pitch) if pitch % 100 != 0 => push(Problem::FinePitch(pitch)),
+            _ => {}
+        }
+    }
+    report
+}
+
+/// How `fold` brings notes into the range of a note block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldStrategy {
+    /// Moves the key by whole octaves, which keeps the instrument but changes the melody.
+    Octave,
+    /// Switches to a sibling instrument that plays the same pitch within range, like Double Bass and Guitar.
+    /// Notes without a fitting sibling are moved by whole octaves.
+    PreferSibling,
+}
+
+/// A note that was changed by `fold`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FoldChange {
+    pub tick: Tick,
+    pub layer: usize,
+    pub old_instrument: Instrument,
+    pub old_key: Key,
+    pub old_pitch: Option<i16>,
+    pub new_instrument: Instrument,
+    pub new_key: Key,
+}
+
+/// Describes what `fold` changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FoldReport {
+    /// Amount of notes that were moved by whole octaves.
+    pub octave_shifted: usize,
+    /// Amount of notes that were switched to a sibling instrument.
+    pub switched_instruments: usize,
+    /// Amount of notes whose fine pitch was not a multiple of 100 cents and had to be rounded.
+    pub fine_pitch: usize,
+    /// Every changed note, ordered by tick and then by layer.
+    pub changes: Vec<FoldChange>,
+}
+
+/// Brings every note with a vanilla instrument into the range of a note block.
+/// The fine pitch is rounded to whole keys and folded into the key.
+/// Notes with custom instruments are left unchanged, since they can not be played in vanilla anyway.
+pub fn fold(nbs: &mut Nbs, strategy: FoldStrategy) -> FoldReport {
+    let vannila_instrument_count = nbs.header.vannila_instrument_count().unwrap_or(16);
+    let mut report = FoldReport::default();
+    for (tick, layer, note) in nbs.noteblocks.events_mut() {
+        if note.instrument.is_custom() {
+            continue;
+        }
+        let old = (note.instrument, note.key, note.pitch);
+        let mut key = effective_key(note.key, note.pitch);
+        let in_range = |key: i32| (MIN_KEY.get() as i32..=MAX_KEY.get() as i32).contains(&key);
+        if !in_range(key) && strategy == FoldStrategy::PreferSibling {
+            let sibling = siblings(note.instrument)
+                .into_iter()
+                .filter(|(i, _)| u8::from(*i) < vannila_instrument_count)
+                .map(|(i, octaves)| (i, key - 12 * octaves as i32))
+                .find(|&(_, key)| in_range(key));
+            if let Some((sibling, sibling_key)) = sibling {
+                note.instrument = sibling;
+                key = sibling_key;
+                report.switched_instruments += 1;
+            }
+        }
+        if !in_range(key) {
+            report.octave_shifted += 1;
+        }
+        note.key = fold_octaves(key);
+        if let Some(pitch) = note.pitch {
+            if pitch % 100 != 0 {
+                report.fine_pitch += 1;
+            }
+            note.pitch = Some(0);
+        }
+        if (note.instrument, note.key, note.pitch) != old {
+            report.changes.push(FoldChange {
+                tick,
+                layer,
+                old_instrument: old.0,
+                old_key: old.1,
+                old_pitch: old.2,
+                new_instrument: note.instrument,
+                new_key: note.key,
+            });
+        }
+    }
+    report
+}
@@ -0,0 +1,42 @@
+use nbs::{
+    clipboard::Selection,
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    Nbs, NbsFormat, Tick,
+};
+
+fn song(ticks: &[Tick]) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut layer = Layer::from_format(format);
+    for &tick in ticks {
+        let mut note = Note::new(instrument::PIANO, Key::default(), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        layer.notes.insert(tick, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.fix();
+    nbs
+}
+
+#[test]
+fn selections_spanning_every_tick() {
+    let mut nbs = song(&[-2, 0, 5]);
+    let selection = Selection::new(-2..Tick::MAX, 0..1);
+    let fragment = nbs.copy(&selection);
+    let ticks: Vec<Tick> = fragment.layers[0].keys().copied().collect();
+    assert_eq!(ticks, [0, 2, 7]);
+
+    // Only the first `Tick::MAX` ticks of the selection can be stored relative to its start.
+    let fragment = nbs.cut(&Selection::new(Tick::MIN..Tick::MAX, 0..1));
+    let ticks: Vec<Tick> = fragment.layers[0].keys().copied().collect();
+    assert_eq!(ticks, [Tick::MAX - 1]);
+    assert_eq!(nbs.noteblocks.layers[0].notes.len(), 2);
+}
@@ -0,0 +1,82 @@
+use nbs::{
+    combine::TempoMode,
+    error::NbsError,
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstrumentInfo, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    Nbs, NbsFormat,
+};
+
+/// Builds a song of version 5 with a note at tick 0, unless it is `empty`.
+/// The note uses the first of `custom` custom instruments, which are named after the tempo so songs with different tempos share none.
+fn song(song_tempo: i16, custom: usize, empty: bool) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut custom_instruments = CustomInstruments::new();
+    for index in 0..custom {
+        let info = CustomInstrumentInfo {
+            instrument: Instrument::Custom(0),
+            name: format!("{} {}", song_tempo, index),
+            file_name: String::new(),
+            pitch: Key::default(),
+            press_key: false,
+        };
+        custom_instruments.add(16, info).unwrap();
+    }
+    let mut layer = Layer::from_format(format);
+    if !empty {
+        let instrument = match custom {
+            0 => instrument::PIANO,
+            _ => Instrument::Custom(16),
+        };
+        let mut note = Note::new(instrument, Key::default(), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        layer.notes.insert(0, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, custom_instruments);
+    nbs.header.song_tempo = song_tempo;
+    nbs.fix();
+    nbs
+}
+
+fn encode(nbs: &Nbs) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    nbs.encode(&mut buffer).unwrap();
+    buffer
+}
+
+#[test]
+fn full_instruments_change_nothing() {
+    // With 16 vanilla instruments the ids of 240 custom instruments reach 255, which leaves no room for another one.
+    let mut nbs = song(1000, 240, true);
+    let original = encode(&nbs);
+    let result = nbs.append(&song(2000, 1, false), TempoMode::TempoChanger);
+    assert!(matches!(result, Err(NbsError::CountOutOfRange { .. })));
+    assert_eq!(encode(&nbs), original);
+}
+
+#[test]
+fn no_room_for_tempo_changer_changes_nothing() {
+    let mut nbs = song(1000, 240, false);
+    let original = encode(&nbs);
+    let result = nbs.append(&song(2000, 0, false), TempoMode::TempoChanger);
+    assert!(matches!(result, Err(NbsError::CountOutOfRange { .. })));
+    assert_eq!(encode(&nbs), original);
+}
+
+#[test]
+fn tempo_changer_is_added() {
+    let mut nbs = song(1000, 239, false);
+    let report = nbs
+        .append(&song(2000, 0, false), TempoMode::TempoChanger)
+        .unwrap();
+    assert!(report.tempo_changer);
+    assert!(nbs.custom_instruments.tempo_changer().is_some());
+    assert_eq!(nbs.tempo_map().ticks_per_second(1), 20.0);
+}
@@ -0,0 +1,115 @@
+use nbs::{
+    conversion::ConversionReport,
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments},
+        layer::Layer,
+        note::Note,
+        value::{Key, Panning, Velocity},
+        NoteBlocks,
+    },
+    Nbs, NbsFormat, Tick,
+};
+
+/// Builds a song of version 5 that uses every field the older formats lack.
+fn song() -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let notes = [
+        Note::new(
+            instrument::PIANO,
+            Key::default(),
+            Velocity::new(50),
+            Panning::new(150),
+            None,
+        ),
+        Note::new(instrument::PIANO, Key::default(), None, None, Some(50)),
+        Note::new(instrument::PIANO, Key::MAX, None, None, Some(100)),
+        Note::new(instrument::PLING, Key::default(), None, None, None),
+    ];
+    let mut layer = Layer::from_format(format);
+    layer.locked = Some(true);
+    layer.solo = Some(true);
+    layer.stereo = Panning::new(50);
+    for (tick, mut note) in notes.iter().cloned().enumerate() {
+        note.convert_to(format, &mut Default::default());
+        layer.notes.insert(tick as Tick, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    noteblocks.layers.push(Layer::from_format(format));
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.header.is_loop = Some(true);
+    nbs.fix();
+    nbs
+}
+
+/// Encodes and decodes the song, which fails if the conversion left a field the format can not hold.
+fn round_trip(nbs: &Nbs) -> Nbs {
+    let mut buffer = Vec::new();
+    nbs.encode(&mut buffer).unwrap();
+    Nbs::decode(&mut &buffer[..]).unwrap()
+}
+
+#[test]
+fn downgrade_to_classic_reports_everything_lost() {
+    let mut nbs = song();
+    let report = nbs.convert_to(NbsFormat::NoteBlockStudio);
+    let expected = ConversionReport {
+        velocity: 1,
+        panning: 1,
+        fine_pitch: 1,
+        clamped_keys: 1,
+        replaced_instruments: 1,
+        layer_locks: 1,
+        layer_solos: 1,
+        layer_stereo: 1,
+        loop_settings: true,
+    };
+    assert_eq!(report, expected);
+    assert!(nbs.validate().is_empty());
+
+    let decoded = round_trip(&nbs);
+    assert_eq!(decoded.format(), NbsFormat::NoteBlockStudio);
+    let notes: Vec<_> = decoded
+        .noteblocks
+        .events()
+        .map(|(tick, _, note)| (tick, note.instrument, note.key.get()))
+        .collect();
+    // The fine pitch is rounded into the key, so 50 cents round up and 100 cents are clamped at the top.
+    let expected = [
+        (0, instrument::PIANO, 45),
+        (1, instrument::PIANO, 46),
+        (2, instrument::PIANO, 87),
+        (3, instrument::PIANO, 45),
+    ];
+    assert_eq!(notes, expected);
+}
+
+#[test]
+fn downgrade_to_version_4_only_loses_solos() {
+    let mut nbs = song();
+    let report = nbs.convert_to(NbsFormat::OpenNoteBlockStudio(4));
+    let expected = ConversionReport {
+        layer_solos: 1,
+        ..Default::default()
+    };
+    assert_eq!(report, expected);
+    assert!(!report.is_lossless());
+    let decoded = round_trip(&nbs);
+    assert_eq!(decoded.noteblocks.layers[0].locked, Some(true));
+    assert_eq!(decoded.noteblocks.layers[0].solo, None);
+    assert_eq!(decoded.header.is_loop, Some(true));
+}
+
+#[test]
+fn upgrade_is_lossless() {
+    let mut nbs = song();
+    nbs.convert_to(NbsFormat::OpenNoteBlockStudio(1));
+    let report = nbs.convert_to(NbsFormat::OpenNoteBlockStudio(5));
+    assert!(report.is_lossless());
+    // Every field the new format introduces is filled.
+    assert!(nbs.validate().is_empty());
+    let layer = &round_trip(&nbs).noteblocks.layers[0];
+    assert_eq!(layer.solo, Some(false));
+    assert_eq!(layer.notes[&1].pitch, Some(0));
+}
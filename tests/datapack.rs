@@ -0,0 +1,123 @@
+use nbs::{
+    datapack::{self, DatapackOptions},
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments},
+        layer::Layer,
+        note::Note,
+        value::{Key, Panning, Volume},
+        NoteBlocks,
+    },
+    Nbs, NbsFormat,
+};
+use std::convert::TryInto;
+
+/// Builds a song at 10 ticks per second.
+/// The first layer plays the piano at tick 0 and an octave higher at tick 2,
+/// the second layer is at half volume and panned to the right and plays the bell at tick 2.
+fn song() -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks
+        .layers
+        .resize_with(2, || Layer::from_format(format));
+    let notes = [
+        (0, 0, instrument::PIANO, 45),
+        (0, 2, instrument::PIANO, 57),
+        (1, 2, instrument::BELL, 45),
+    ];
+    for &(layer, tick, instrument, key) in &notes {
+        let mut note = Note::new(instrument, Key::from_raw(key), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        noteblocks.layers[layer].notes.insert(tick, note);
+    }
+    noteblocks.layers[1].volume = Volume::saturating(50);
+    noteblocks.layers[1].stereo = Some(Panning::MAX);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.header.song_tempo = 1000;
+    nbs.fix();
+    nbs
+}
+
+/// Reads the entries of a zip archive of stored entries as `(name, content)`.
+fn unzip(zip: &[u8]) -> Vec<(String, String)> {
+    let u16_at = |offset: usize| u16::from_le_bytes(zip[offset..offset + 2].try_into().unwrap());
+    let u32_at = |offset: usize| u32::from_le_bytes(zip[offset..offset + 4].try_into().unwrap());
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while u32_at(offset) == 0x0403_4B50 {
+        assert_eq!(u16_at(offset + 8), 0, "only stored entries are expected");
+        let size = u32_at(offset + 18) as usize;
+        let name_length = u16_at(offset + 26) as usize;
+        let name = &zip[offset + 30..offset + 30 + name_length];
+        let data = &zip[offset + 30 + name_length..offset + 30 + name_length + size];
+        entries.push((
+            String::from_utf8(name.to_vec()).unwrap(),
+            String::from_utf8(data.to_vec()).unwrap(),
+        ));
+        offset += 30 + name_length + size;
+    }
+    // The central directory follows the entries.
+    assert_eq!(u32_at(offset), 0x0201_4B50);
+    entries
+}
+
+fn encode(options: &DatapackOptions) -> Vec<(String, String)> {
+    let mut zip = Vec::new();
+    datapack::encode(&song(), options, &mut zip).unwrap();
+    unzip(&zip)
+}
+
+#[test]
+fn functions() {
+    let mut options = DatapackOptions::new("song");
+    options.description = String::from("A \"song\"");
+    let entries = encode(&options);
+    let names: Vec<_> = entries.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "pack.mcmeta",
+            "data/song/function/play.mcfunction",
+            "data/song/function/stop.mcfunction",
+            "data/song/function/tick/0.mcfunction",
+            "data/song/function/tick/2.mcfunction",
+        ]
+    );
+    let content = |index: usize| entries[index].1.as_str();
+    assert_eq!(
+        content(0),
+        "{\n  \"pack\": {\n    \"pack_format\": 48,\n    \"description\": \"A \\\"song\\\"\"\n  }\n}\n"
+    );
+    assert_eq!(content(1), "function song:stop\nfunction song:tick/0\n");
+    assert_eq!(
+        content(2),
+        "schedule clear song:tick/0\nschedule clear song:tick/2\n"
+    );
+    // Tick 2 is 0.2 seconds later, which are 4 game ticks.
+    assert_eq!(
+        content(3),
+        "execute as @a at @s run playsound minecraft:block.note_block.harp record @s ^ ^ ^ 1 1\n\
+         schedule function song:tick/2 4t\n"
+    );
+    // Half of the way to the right is a block to the right of the player, which is negative in local coordinates.
+    assert_eq!(
+        content(4),
+        "execute as @a at @s run playsound minecraft:block.note_block.harp record @s ^ ^ ^ 1 2\n\
+         execute as @a at @s run playsound minecraft:block.note_block.bell record @s ^-1 ^ ^ 0.5 1\n"
+    );
+}
+
+#[test]
+fn old_pack_formats_use_the_functions_folder() {
+    let mut options = DatapackOptions::new("song");
+    options.pack_format = 44;
+    let entries = encode(&options);
+    assert_eq!(entries[1].0, "data/song/functions/play.mcfunction");
+}
+
+#[test]
+fn invalid_namespace_fails() {
+    let options = DatapackOptions::new("My Song");
+    assert!(datapack::encode(&song(), &options, &mut Vec::new()).is_err());
+}
@@ -0,0 +1,99 @@
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::{Key, Volume},
+        NoteBlocks,
+    },
+    Nbs, NbsFormat, Tick,
+};
+
+/// Builds a song with a layer for every list of notes, `(tick, instrument)`.
+/// The key of every note is the index of its layer, and the volume of every layer is 10 times its index.
+fn song(layers: &[&[(Tick, Instrument)]]) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut noteblocks = NoteBlocks::new();
+    for (index, notes) in layers.iter().enumerate() {
+        let mut layer = Layer::from_format(format);
+        layer.volume = Volume::saturating(index as i32 * 10);
+        for &(tick, instrument) in notes.iter() {
+            let mut note = Note::new(instrument, Key::saturating(index as i32), None, None, None);
+            note.convert_to(format, &mut Default::default());
+            layer.notes.insert(tick, note);
+        }
+        noteblocks.layers.push(layer);
+    }
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.fix();
+    nbs
+}
+
+/// Returns the notes of a layer as `(tick, key)`, where the key tells which layer the note came from.
+fn notes(nbs: &Nbs, layer: usize) -> Vec<(Tick, u8)> {
+    nbs.noteblocks.layers[layer]
+        .notes
+        .iter()
+        .map(|(&tick, note)| (tick, note.key.get()))
+        .collect()
+}
+
+#[test]
+fn overlapping_notes_spill_into_new_layers() {
+    let piano = instrument::PIANO;
+    let mut nbs = song(&[
+        &[(0, piano), (1, piano)],
+        &[(1, piano), (2, piano)],
+        &[(5, piano)],
+        &[(1, piano), (3, piano)],
+    ]);
+    let layers = nbs.merge_layers(&[3, 1, 0]);
+    assert_eq!(layers, 0..3);
+    // Lower layers keep their place on colliding ticks.
+    assert_eq!(notes(&nbs, 0), [(0, 0), (1, 0), (2, 1), (3, 3)]);
+    assert_eq!(notes(&nbs, 1), [(1, 1)]);
+    assert_eq!(notes(&nbs, 2), [(1, 3)]);
+    // The layer that was not merged comes after the merged ones.
+    assert_eq!(notes(&nbs, 3), [(5, 2)]);
+    for layer in &nbs.noteblocks.layers[layers] {
+        assert_eq!(layer.volume, Volume::saturating(0));
+    }
+    assert_eq!(nbs.header.layer_count, 4);
+}
+
+#[test]
+fn layers_are_split_by_instrument() {
+    let mut nbs = song(&[
+        &[(0, instrument::PIANO)],
+        &[
+            (0, instrument::GUITAR),
+            (1, instrument::PIANO),
+            (2, instrument::GUITAR),
+            (3, Instrument::Vanilla(15)),
+        ],
+        &[(4, instrument::PIANO)],
+    ]);
+    let layers = nbs.split_layer_by_instrument(1);
+    assert_eq!(layers, 1..4);
+    let instruments: Vec<Vec<_>> = nbs.noteblocks.layers[layers.clone()]
+        .iter()
+        .map(|layer| layer.notes.values().map(|note| note.instrument).collect())
+        .collect();
+    assert_eq!(
+        instruments,
+        [
+            vec![instrument::PIANO],
+            vec![instrument::GUITAR, instrument::GUITAR],
+            vec![Instrument::Vanilla(15)],
+        ]
+    );
+    for layer in &nbs.noteblocks.layers[layers] {
+        assert_eq!(layer.volume, Volume::saturating(10));
+    }
+    assert_eq!(notes(&nbs, 0), [(0, 0)]);
+    assert_eq!(notes(&nbs, 4), [(4, 2)]);
+    assert_eq!(nbs.header.layer_count, 5);
+    // An empty layer stays a single layer.
+    assert_eq!(song(&[&[]]).split_layer_by_instrument(0), 0..1);
+}
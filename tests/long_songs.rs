@@ -0,0 +1,91 @@
+use nbs::{
+    error::NbsError,
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    Nbs, NbsFormat, Tick,
+};
+
+fn song(format: NbsFormat, ticks: &[Tick]) -> Nbs {
+    let mut noteblocks = NoteBlocks::new();
+    let mut layer = Layer::from_format(format);
+    for &tick in ticks {
+        let mut note = Note::new(instrument::PIANO, Key::default(), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        layer.notes.insert(tick, note);
+    }
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.fix();
+    nbs
+}
+
+#[test]
+fn notes_past_tick_32767_round_trip() {
+    let ticks = [0, 30000, 40000, 60000];
+    let nbs = song(NbsFormat::OpenNoteBlockStudio(2), &ticks);
+    assert!(nbs.validate().is_empty());
+    let mut buffer = Vec::new();
+    nbs.encode(&mut buffer).unwrap();
+    let decoded = Nbs::decode(&mut &buffer[..]).unwrap();
+    let decoded_ticks: Vec<Tick> = decoded.noteblocks.layers[0].notes.keys().copied().collect();
+    assert_eq!(decoded_ticks, ticks);
+}
+
+#[test]
+fn jumps_beyond_16_bits_fail() {
+    let nbs = song(NbsFormat::OpenNoteBlockStudio(2), &[0, 40000]);
+    assert!(!nbs.validate().is_empty());
+    match nbs.encode(&mut Vec::new()) {
+        Err(NbsError::TickOutOfRange { field, tick }) => {
+            assert_eq!(field, "tick_jumps");
+            assert_eq!(tick, 40000);
+        }
+        result => panic!("unexpected result {:?}", result.err()),
+    }
+}
+
+#[test]
+fn song_length_beyond_16_bits_fails() {
+    let nbs = song(NbsFormat::OpenNoteBlockStudio(5), &[0, 30000, 40000]);
+    assert!(!nbs.validate().is_empty());
+    match nbs.encode(&mut Vec::new()) {
+        Err(NbsError::TickOutOfRange { field, .. }) => assert_eq!(field, "song_length"),
+        result => panic!("unexpected result {:?}", result.err()),
+    }
+}
+
+#[test]
+fn layers_beyond_16_bits_fail() {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut nbs = song(format, &[0]);
+    for _ in 0..i16::MAX {
+        let index = nbs.noteblocks.layers.len();
+        nbs.insert_layer(index, Layer::from_format(format));
+    }
+    assert_eq!(nbs.header.layer_count, i16::MAX);
+    match nbs.encode(&mut Vec::new()) {
+        Err(NbsError::CountOutOfRange { field, count }) => {
+            assert_eq!(field, "layer_count");
+            assert_eq!(count, 32768);
+        }
+        result => panic!("unexpected result {:?}", result.err()),
+    }
+}
+
+#[test]
+fn negative_ticks_fail() {
+    let nbs = song(NbsFormat::OpenNoteBlockStudio(5), &[-1, 0]);
+    match nbs.encode(&mut Vec::new()) {
+        Err(NbsError::TickOutOfRange { field, tick }) => {
+            assert_eq!(field, "tick_jumps");
+            assert_eq!(tick, -1);
+        }
+        result => panic!("unexpected result {:?}", result.err()),
+    }
+}
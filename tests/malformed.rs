@@ -0,0 +1,217 @@
+use nbs::{
+    error::{Limit, NbsError, Repair},
+    header::Header,
+    io::DecodeLimits,
+    noteblocks::{
+        instrument::{self, CustomInstrumentInfo, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    Nbs, NbsFormat,
+};
+
+/// The offset of the layer count in the header of version 5.
+const LAYER_COUNT_OFFSET: usize = 6;
+/// The offset of the length of the song name in the header of version 5.
+const SONG_NAME_OFFSET: usize = 8;
+
+/// Encodes a song of version 5 with a note at tick 100 in the last of three layers and two custom instruments.
+fn song() -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks
+        .layers
+        .resize_with(3, || Layer::from_format(format));
+    let mut note = Note::new(instrument::PIANO, Key::default(), None, None, None);
+    note.convert_to(format, &mut Default::default());
+    noteblocks.layers[2].notes.insert(100, note);
+    let mut custom_instruments = CustomInstruments::new();
+    for name in &["first", "second"] {
+        custom_instruments
+            .add(
+                16,
+                CustomInstrumentInfo {
+                    instrument: Instrument::Custom(0),
+                    name: name.to_string(),
+                    file_name: String::new(),
+                    pitch: Key::default(),
+                    press_key: false,
+                },
+            )
+            .unwrap();
+    }
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, custom_instruments);
+    nbs.fix();
+    nbs
+}
+
+fn encode(nbs: &Nbs) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    nbs.encode(&mut buffer).unwrap();
+    buffer
+}
+
+/// Decodes with one changed limit and returns the limit that was exceeded.
+fn exceeded_limit(limits: DecodeLimits) -> Option<Limit> {
+    match Nbs::decode_with_limits(&mut &encode(&song())[..], &limits) {
+        Err(e) => match e.without_context() {
+            NbsError::LimitExceeded { limit, .. } => Some(*limit),
+            _ => None,
+        },
+        Ok(_) => None,
+    }
+}
+
+#[test]
+fn valid_song_decodes() {
+    let buffer = encode(&song());
+    assert!(Nbs::decode_with_limits(&mut &buffer[..], &DecodeLimits::default()).is_ok());
+}
+
+#[test]
+fn note_beyond_layer_count_fails() {
+    let mut nbs = song();
+    nbs.header.layer_count = 2;
+    assert!(Nbs::decode(&mut &encode(&nbs)[..]).is_err());
+}
+
+#[test]
+fn negative_string_length_fails() {
+    let mut buffer = encode(&song());
+    buffer[SONG_NAME_OFFSET..SONG_NAME_OFFSET + 4].copy_from_slice(&(-1i32).to_le_bytes());
+    assert!(Nbs::decode(&mut &buffer[..]).is_err());
+}
+
+#[test]
+fn huge_string_length_fails() {
+    let mut buffer = encode(&song());
+    buffer[SONG_NAME_OFFSET..SONG_NAME_OFFSET + 4].copy_from_slice(&i32::MAX.to_le_bytes());
+    assert!(Nbs::decode(&mut &buffer[..]).is_err());
+}
+
+#[test]
+fn string_length_limit() {
+    let mut nbs = song();
+    nbs.header.song_name = String::from("A song name longer than the limit");
+    let limits = DecodeLimits {
+        max_string_length: 8,
+        ..DecodeLimits::default()
+    };
+    match Nbs::decode_with_limits(&mut &encode(&nbs)[..], &limits) {
+        Err(e) => assert!(matches!(
+            e.without_context(),
+            NbsError::LimitExceeded {
+                limit: Limit::StringLength,
+                ..
+            }
+        )),
+        Ok(_) => panic!("the song name exceeds the limit"),
+    }
+}
+
+#[test]
+fn layers_limit() {
+    let limits = DecodeLimits {
+        max_layers: 2,
+        ..DecodeLimits::default()
+    };
+    assert_eq!(exceeded_limit(limits), Some(Limit::Layers));
+}
+
+#[test]
+fn ticks_limit() {
+    let limits = DecodeLimits {
+        max_ticks: 99,
+        ..DecodeLimits::default()
+    };
+    assert_eq!(exceeded_limit(limits), Some(Limit::Ticks));
+}
+
+#[test]
+fn custom_instruments_limit() {
+    let limits = DecodeLimits {
+        max_custom_instruments: 1,
+        ..DecodeLimits::default()
+    };
+    assert_eq!(exceeded_limit(limits), Some(Limit::CustomInstruments));
+}
+
+#[test]
+fn bogus_layer_count() {
+    let mut nbs = song();
+    nbs.custom_instruments = CustomInstruments::new();
+    let mut buffer = encode(&nbs);
+    buffer[LAYER_COUNT_OFFSET..LAYER_COUNT_OFFSET + 2].copy_from_slice(&i16::MAX.to_le_bytes());
+    assert!(Nbs::decode(&mut &buffer[..]).is_err());
+    // Only the layers whose information is there are created.
+    let (nbs, warnings) = Nbs::decode_lenient(&mut &buffer[..], &DecodeLimits::default()).unwrap();
+    assert!(!warnings.is_empty());
+    assert_eq!(nbs.noteblocks.layers.len(), 3);
+    assert_eq!(nbs.header.layer_count, 3);
+    assert_eq!(nbs.noteblocks.layers[2].notes.len(), 1);
+}
+
+/// Decodes leniently and returns the song with the repairs that were made.
+fn repairs(buffer: &[u8]) -> (Nbs, Vec<Repair>) {
+    let (nbs, warnings) = Nbs::decode_lenient(&mut &buffer[..], &DecodeLimits::default()).unwrap();
+    (
+        nbs,
+        warnings.into_iter().map(|warning| warning.repair).collect(),
+    )
+}
+
+#[test]
+fn missing_layers_are_added() {
+    let mut nbs = song();
+    nbs.header.layer_count = 2;
+    let (nbs, repairs) = repairs(&encode(&nbs));
+    assert_eq!(repairs, [Repair::AddedLayers(1)]);
+    assert_eq!(nbs.noteblocks.layers.len(), 3);
+    assert_eq!(nbs.header.layer_count, 3);
+    assert_eq!(nbs.noteblocks.layers[2].notes.len(), 1);
+}
+
+#[test]
+fn invalid_strings_are_replaced() {
+    let mut nbs = song();
+    nbs.header.song_name = String::from("abc");
+    let mut buffer = encode(&nbs);
+    buffer[SONG_NAME_OFFSET + 4] = 0xFF;
+    assert!(Nbs::decode(&mut &buffer[..]).is_err());
+    let (nbs, repairs) = repairs(&buffer);
+    assert_eq!(repairs, [Repair::LossyString]);
+    assert_eq!(nbs.header.song_name, "\u{FFFD}bc");
+}
+
+#[test]
+fn truncated_custom_instruments_are_dropped() {
+    let buffer = encode(&song());
+    let buffer = &buffer[..buffer.len() - 1];
+    assert!(Nbs::decode(&mut &buffer[..]).is_err());
+    let (nbs, repairs) = repairs(buffer);
+    assert_eq!(repairs, [Repair::MissingCustomInstruments]);
+    let names: Vec<_> = nbs
+        .custom_instruments
+        .iter()
+        .map(|info| info.name.as_str())
+        .collect();
+    assert_eq!(names, ["first"]);
+}
+
+#[test]
+fn truncated_note_blocks_keep_the_decoded_notes() {
+    let original = std::fs::read("tests/1.nbs").unwrap();
+    let notes = Nbs::decode(&mut &original[..])
+        .unwrap()
+        .noteblocks
+        .events()
+        .count();
+    let (nbs, repairs) = repairs(&original[..200]);
+    assert_eq!(repairs[0], Repair::TruncatedNoteBlocks);
+    assert!(repairs.contains(&Repair::MissingLayerInfo));
+    let decoded = nbs.noteblocks.events().count();
+    assert!(decoded > 0 && decoded < notes);
+    assert_eq!(nbs.header.layer_count as usize, nbs.noteblocks.layers.len());
+}
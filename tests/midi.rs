@@ -0,0 +1,128 @@
+use nbs::{
+    error::NbsError,
+    header::Header,
+    midi::{self, ImportOptions},
+    noteblocks::{
+        instrument::{self, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    Nbs, NbsFormat, Tick,
+};
+
+/// Builds a Type-0 file with one note, which starts after `delay` pulses.
+fn file(division: u16, delay: u8) -> Vec<u8> {
+    let track = [
+        delay, 0x90, 60, 100, // note on
+        0x10, 0x80, 60, 0, // note off
+        0x00, 0xFF, 0x2F, 0x00, // end of track
+    ];
+    let mut file = Vec::new();
+    file.extend_from_slice(b"MThd");
+    file.extend_from_slice(&6u32.to_be_bytes());
+    file.extend_from_slice(&0u16.to_be_bytes());
+    file.extend_from_slice(&1u16.to_be_bytes());
+    file.extend_from_slice(&division.to_be_bytes());
+    file.extend_from_slice(b"MTrk");
+    file.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    file.extend_from_slice(&track);
+    file
+}
+
+#[test]
+fn smpte_timing() {
+    // 25 frames per second with 40 pulses each, so every pulse is a millisecond.
+    let division = u16::from_be_bytes([-25i8 as u8, 40]);
+    let nbs = midi::decode(&mut &file(division, 100)[..], &ImportOptions::default()).unwrap();
+    let ticks: Vec<_> = nbs.noteblocks.events().map(|(tick, _, _)| tick).collect();
+    assert_eq!(ticks, [1]);
+}
+
+#[test]
+fn invalid_smpte_timing_fails() {
+    for &division in &[0x8028, 0xE700] {
+        let result = midi::decode(&mut &file(division, 0)[..], &ImportOptions::default());
+        assert!(result.is_err(), "division {:#06x}", division);
+    }
+}
+
+/// Builds a song with one piano note at each tick.
+fn song(ticks: &[Tick]) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut layer = Layer::from_format(format);
+    for &tick in ticks {
+        let mut note = Note::new(instrument::PIANO, Key::default(), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        layer.notes.insert(tick, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.fix();
+    nbs
+}
+
+#[test]
+fn notes_before_tick_0_are_skipped() {
+    let mut smf = Vec::new();
+    midi::encode(&song(&[-4, 0, 4]), &Default::default(), &mut smf).unwrap();
+    let nbs = midi::decode(&mut &smf[..], &ImportOptions::default()).unwrap();
+    let ticks: Vec<_> = nbs.noteblocks.events().map(|(tick, _, _)| tick).collect();
+    assert_eq!(ticks, [0, 4]);
+}
+
+#[test]
+fn late_notes_fail() {
+    let nbs = song(&[0, Tick::MAX]);
+    let result = midi::encode(&nbs, &Default::default(), &mut Vec::new());
+    assert!(matches!(result, Err(NbsError::InvalidData(_))));
+}
+
+#[test]
+fn too_many_layers_fail() {
+    let mut nbs = song(&[0]);
+    let format = nbs.format();
+    nbs.noteblocks
+        .layers
+        .resize_with(u16::MAX as usize, || Layer::from_format(format));
+    match midi::encode(&nbs, &Default::default(), &mut Vec::new()) {
+        Err(NbsError::CountOutOfRange { field, count }) => {
+            assert_eq!(field, "track_count");
+            assert_eq!(count, 65536);
+        }
+        result => panic!("unexpected result {:?}", result.err()),
+    }
+}
+
+#[test]
+fn layers_sharing_channels_keep_their_instruments() {
+    // More layers than melodic channels, every one with another instrument than its neighbours.
+    let mut nbs = song(&[]);
+    let format = nbs.format();
+    let mut expected = Vec::new();
+    for index in 0..20u8 {
+        let instrument = Instrument::Vanilla(index % 16);
+        let mut note = Note::new(instrument, Key::default(), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        let mut layer = Layer::from_format(format);
+        for &tick in &[0, 4 + index as Tick] {
+            layer.notes.insert(tick, note.clone());
+            expected.push((tick, instrument));
+        }
+        nbs.noteblocks.layers.push(layer);
+    }
+    nbs.fix();
+    let mut smf = Vec::new();
+    midi::encode(&nbs, &Default::default(), &mut smf).unwrap();
+    let imported = midi::decode(&mut &smf[..], &ImportOptions::default()).unwrap();
+    let mut instruments: Vec<_> = imported
+        .noteblocks
+        .events()
+        .map(|(tick, _, note)| (tick, note.instrument))
+        .collect();
+    instruments.sort_by_key(|&(tick, instrument)| (tick, u8::from(instrument)));
+    expected.sort_by_key(|&(tick, instrument)| (tick, u8::from(instrument)));
+    assert_eq!(instruments, expected);
+}
@@ -0,0 +1,166 @@
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstrumentInfo, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    player::{ManualClock, Player, PlayerState},
+    Nbs, NbsFormat, Tick,
+};
+use std::time::Duration;
+
+/// Builds a song at 10 ticks per second, so every tick lasts 100 milliseconds.
+/// Every layer gets a piano note at each of its ticks.
+fn song(layers: &[&[Tick]]) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut noteblocks = NoteBlocks::new();
+    for ticks in layers {
+        let mut layer = Layer::from_format(format);
+        for &tick in ticks.iter() {
+            let note = Note::new(instrument::PIANO, Key::default(), None, None, None);
+            layer.notes.insert(tick, note);
+        }
+        noteblocks.layers.push(layer);
+    }
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.header.song_tempo = 1000;
+    nbs.fix();
+    nbs
+}
+
+/// Polls the player and returns the emitted notes as `(tick, layer_index)`.
+fn poll(player: &mut Player<ManualClock>) -> Vec<(Tick, usize)> {
+    player
+        .poll()
+        .into_iter()
+        .map(|(tick, layer_index, _)| (tick, layer_index))
+        .collect()
+}
+
+fn advance(player: &Player<ManualClock>, millis: u64) {
+    player.clock().advance(Duration::from_millis(millis));
+}
+
+#[test]
+fn every_note_is_emitted_once() {
+    let nbs = song(&[&[0, 1, 2, 5, 9], &[1, 3, 9]]);
+    let mut player = Player::with_clock(&nbs, ManualClock::new());
+    player.play();
+    let mut events = Vec::new();
+    // Polls that do not line up with the ticks.
+    while !player.is_stopped() {
+        events.extend(poll(&mut player));
+        advance(&player, 30);
+    }
+    let expected = [
+        (0, 0),
+        (1, 0),
+        (1, 1),
+        (2, 0),
+        (3, 1),
+        (5, 0),
+        (9, 0),
+        (9, 1),
+    ];
+    assert_eq!(events, expected);
+    assert!(poll(&mut player).is_empty());
+}
+
+#[test]
+fn notes_are_due_when_their_tick_starts() {
+    let nbs = song(&[&[0, 1, 2]]);
+    let mut player = Player::with_clock(&nbs, ManualClock::new());
+    player.play();
+    assert_eq!(poll(&mut player), [(0, 0)]);
+    advance(&player, 99);
+    assert!(poll(&mut player).is_empty());
+    advance(&player, 1);
+    assert_eq!(poll(&mut player), [(1, 0)]);
+}
+
+#[test]
+fn pause_keeps_the_position() {
+    let nbs = song(&[&[0, 1, 2, 3, 4]]);
+    let mut player = Player::with_clock(&nbs, ManualClock::new());
+    player.play();
+    advance(&player, 250);
+    assert_eq!(poll(&mut player), [(0, 0), (1, 0), (2, 0)]);
+    player.pause();
+    advance(&player, 1000);
+    assert!(poll(&mut player).is_empty());
+    assert_eq!(player.state(), PlayerState::Paused);
+    assert_eq!(player.position(), Duration::from_millis(250));
+    player.play();
+    advance(&player, 40);
+    assert!(poll(&mut player).is_empty());
+    advance(&player, 10);
+    assert_eq!(poll(&mut player), [(3, 0)]);
+    assert_eq!(player.tick(), 3);
+}
+
+#[test]
+fn seek_emits_the_target_tick() {
+    let nbs = song(&[&[0, 1, 5, 6], &[5]]);
+    let mut player = Player::with_clock(&nbs, ManualClock::new());
+    player.seek(5);
+    assert_eq!(player.state(), PlayerState::Paused);
+    player.play();
+    assert_eq!(poll(&mut player), [(5, 0), (5, 1)]);
+    advance(&player, 100);
+    assert_eq!(poll(&mut player), [(6, 0)]);
+    // Seeking backwards plays the notes again.
+    player.seek(0);
+    assert_eq!(poll(&mut player), [(0, 0)]);
+}
+
+#[test]
+fn loops_stop_after_max_loop_count() {
+    let mut nbs = song(&[&[0, 1, 2]]);
+    nbs.header.is_loop = Some(true);
+    nbs.header.max_loop_count = Some(2);
+    nbs.header.loop_start_tick = Some(1);
+    let mut player = Player::with_clock(&nbs, ManualClock::new());
+    player.play();
+    let mut ticks = Vec::new();
+    for _ in 0..100 {
+        ticks.extend(poll(&mut player).into_iter().map(|(tick, _)| tick));
+        advance(&player, 50);
+    }
+    assert_eq!(ticks, [0, 1, 2, 1, 2, 1, 2]);
+    assert_eq!(player.loops(), 2);
+    assert!(player.is_stopped());
+}
+
+#[test]
+fn solo_layers_and_tempo_changers_are_filtered() {
+    let mut nbs = song(&[&[0, 2], &[0, 1], &[1]]);
+    nbs.noteblocks.layers[0].solo = Some(true);
+    nbs.noteblocks.layers[2].solo = Some(true);
+    let tempo_changer = nbs
+        .custom_instruments
+        .add(
+            16,
+            CustomInstrumentInfo {
+                instrument: Instrument::Custom(0),
+                name: String::from(CustomInstrumentInfo::TEMPO_CHANGER),
+                file_name: String::new(),
+                pitch: Key::default(),
+                press_key: false,
+            },
+        )
+        .unwrap();
+    // Doubles the tempo from tick 1 onwards.
+    let mut note = Note::new(tempo_changer, Key::default(), None, None, Some(300));
+    note.convert_to(nbs.format(), &mut Default::default());
+    nbs.noteblocks.layers[0].notes.insert(1, note);
+    let mut player = Player::with_clock(&nbs, ManualClock::new());
+    player.play();
+    assert_eq!(poll(&mut player), [(0, 0)]);
+    advance(&player, 100);
+    assert_eq!(poll(&mut player), [(1, 2)]);
+    advance(&player, 50);
+    assert_eq!(poll(&mut player), [(2, 0)]);
+}
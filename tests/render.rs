@@ -0,0 +1,80 @@
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments},
+        layer::Layer,
+        note::Note,
+        value::{Key, Panning},
+        NoteBlocks,
+    },
+    render::{Renderer, Sample, WavFormat},
+    Nbs, NbsFormat,
+};
+use std::convert::TryInto;
+
+const SAMPLE_RATE: u32 = 8000;
+
+/// Builds a song with a piano note at ticks 0 and 4.
+fn song() -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut layer = Layer::from_format(format);
+    for &tick in &[0, 4] {
+        let mut note = Note::new(instrument::PIANO, Key::default(), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        layer.notes.insert(tick, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.fix();
+    nbs
+}
+
+/// A renderer with a short sine wave as the piano sound.
+fn renderer() -> Renderer {
+    let sine = (0..SAMPLE_RATE / 10)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / SAMPLE_RATE as f32).sin() * 0.5)
+        .collect();
+    let mut renderer = Renderer::new(SAMPLE_RATE);
+    renderer.set_sample(instrument::PIANO, Sample::new(SAMPLE_RATE, sine));
+    renderer
+}
+
+fn u32_at(wav: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn wav_header_sizes() {
+    let mut wav = Vec::new();
+    renderer()
+        .encode_wav(&song(), WavFormat::Pcm16, &mut wav)
+        .unwrap();
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+    assert_eq!(&wav[8..12], b"WAVE");
+    assert_eq!(u32_at(&wav, 24), SAMPLE_RATE);
+    assert_eq!(u32_at(&wav, 28), SAMPLE_RATE * 4);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(u32_at(&wav, 40) as usize, wav.len() - 44);
+    let samples: Vec<_> = wav[44..]
+        .chunks(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect();
+    assert!(samples.iter().any(|&sample| sample != 0));
+}
+
+#[test]
+fn out_of_range_panning_is_clamped() {
+    let mut nbs = song();
+    // Set after `fix`, which would saturate the panning.
+    let layer = &mut nbs.noteblocks.layers[0];
+    layer.stereo = Some(Panning::from_raw(255));
+    for note in layer.notes.values_mut() {
+        note.panning = Some(Panning::from_raw(255));
+    }
+    let frames = renderer().render(&nbs);
+    assert!(frames.iter().any(|frame| frame[1] != 0.0));
+    // Fully to the right, so nothing is left on the left side, not even an inverted signal.
+    assert!(frames.iter().all(|frame| frame[0] == 0.0));
+}
@@ -0,0 +1,93 @@
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    Nbs, NbsFormat,
+};
+use std::fs;
+
+/// The offset of the song length in the header of version 3 and later.
+const SONG_LENGTH_OFFSET: usize = 4;
+
+/// Builds a song with a locked, a solo and a plain layer, with notes at ticks 0 and 9.
+fn song(format: NbsFormat) -> Nbs {
+    let mut noteblocks = NoteBlocks::new();
+    for &(locked, solo) in &[(true, false), (false, true), (false, false)] {
+        let mut layer = Layer::from_format(format);
+        if format.version() >= 4 {
+            layer.locked = Some(locked);
+        }
+        if format.version() >= 5 {
+            layer.solo = Some(solo);
+        }
+        for &tick in &[0, 9] {
+            let mut note = Note::new(instrument::PIANO, Key::default(), None, None, None);
+            note.convert_to(format, &mut Default::default());
+            layer.notes.insert(tick, note);
+        }
+        noteblocks.layers.push(layer);
+    }
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.fix();
+    nbs
+}
+
+fn encode(nbs: &Nbs) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    nbs.encode(&mut buffer).unwrap();
+    buffer
+}
+
+#[test]
+fn decode_encode_reproduces_the_file() {
+    let original = fs::read("tests/1.nbs").unwrap();
+    let nbs = Nbs::decode(&mut &original[..]).unwrap();
+    let mut encoded = Vec::new();
+    nbs.encode(&mut encoded).unwrap();
+    assert_eq!(encoded, original);
+}
+
+#[test]
+fn version_5_layer_status() {
+    let buffer = encode(&song(NbsFormat::OpenNoteBlockStudio(5)));
+    let nbs = Nbs::decode(&mut &buffer[..]).unwrap();
+    let status: Vec<_> = nbs
+        .noteblocks
+        .layers
+        .iter()
+        .map(|layer| (layer.locked, layer.solo))
+        .collect();
+    assert_eq!(
+        status,
+        [
+            (Some(true), Some(false)),
+            (Some(false), Some(true)),
+            (Some(false), Some(false))
+        ]
+    );
+    assert_eq!(encode(&nbs), buffer);
+}
+
+#[test]
+fn version_5_song_length() {
+    // Version 5 stores the amount of ticks, version 4 the last tick.
+    for &(version, stored) in &[(5, 10), (4, 9)] {
+        let buffer = encode(&song(NbsFormat::OpenNoteBlockStudio(version)));
+        let song_length = &buffer[SONG_LENGTH_OFFSET..SONG_LENGTH_OFFSET + 2];
+        assert_eq!(
+            song_length,
+            (stored as i16).to_le_bytes(),
+            "version {}",
+            version
+        );
+        let nbs = Nbs::decode(&mut &buffer[..]).unwrap();
+        assert_eq!(nbs.header.song_ticks().unwrap(), Some(9));
+        assert!(nbs.validate().is_empty());
+        assert_eq!(encode(&nbs), buffer);
+    }
+}
@@ -0,0 +1,116 @@
+#![cfg(feature = "serde")]
+
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstrumentInfo, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::{Key, Panning, Velocity},
+        NoteBlocks,
+    },
+    Nbs, NbsFormat,
+};
+use std::fs;
+
+fn encode(nbs: &Nbs) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    nbs.encode(&mut buffer).unwrap();
+    buffer
+}
+
+fn json_round_trip(nbs: &Nbs) -> Nbs {
+    let json = serde_json::to_string(nbs).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+/// Builds a song of version 5 with a custom instrument, a loop and values Note Block Studio would not write.
+fn song() -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut custom_instruments = CustomInstruments::new();
+    let custom = custom_instruments
+        .add(
+            16,
+            CustomInstrumentInfo {
+                instrument: Instrument::Custom(0),
+                name: String::from("Custom"),
+                file_name: String::from("custom.ogg"),
+                pitch: Key::new(50).unwrap(),
+                press_key: true,
+            },
+        )
+        .unwrap();
+    let mut layer = Layer::from_format(format);
+    layer.name = String::from("Melody");
+    layer.solo = Some(true);
+    layer.stereo = Panning::new(30);
+    let notes = [
+        Note::new(
+            custom,
+            Key::new(60).unwrap(),
+            Velocity::new(70),
+            Panning::new(180),
+            Some(-35),
+        ),
+        Note::new(instrument::PIANO, Key::default(), None, None, Some(0)),
+    ];
+    for (tick, note) in notes.iter().cloned().enumerate() {
+        layer.notes.insert(tick as i32 * 4, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, custom_instruments);
+    nbs.header.song_name = String::from("Song");
+    nbs.header.is_loop = Some(true);
+    nbs.header.loop_start_tick = Some(3);
+    nbs.fix();
+    // Set after `fix`, which would saturate them.
+    let note = nbs.noteblocks.layers[0].notes.get_mut(&4).unwrap();
+    note.key = Key::from_raw(200);
+    note.velocity = Some(Velocity::from_raw(255));
+    nbs
+}
+
+#[test]
+fn json_round_trip_keeps_the_bytes() {
+    let data = fs::read("tests/1.nbs").unwrap();
+    let nbs = Nbs::decode(&mut &data[..]).unwrap();
+    assert_eq!(encode(&json_round_trip(&nbs)), data);
+}
+
+#[test]
+fn every_format_round_trips() {
+    let formats = [
+        NbsFormat::NoteBlockStudio,
+        NbsFormat::OpenNoteBlockStudio(1),
+        NbsFormat::OpenNoteBlockStudio(4),
+        NbsFormat::OpenNoteBlockStudio(5),
+    ];
+    for &format in &formats {
+        let mut nbs = song();
+        nbs.convert_to(format);
+        let data = encode(&nbs);
+        assert_eq!(encode(&json_round_trip(&nbs)), data, "{:?}", format);
+    }
+}
+
+#[test]
+fn schema() {
+    let json = serde_json::to_value(song()).unwrap();
+    assert_eq!(
+        json["header"]["format"],
+        serde_json::json!({"OpenNoteBlockStudio": 5})
+    );
+    let note = &json["noteblocks"]["layers"][0]["notes"]["0"];
+    assert_eq!(
+        note,
+        &serde_json::json!({
+            "instrument": {"Custom": 16},
+            "key": 60,
+            "velocity": 70,
+            "panning": 180,
+            "pitch": -35,
+        })
+    );
+    assert_eq!(json["custom_instruments"][0]["pitch"], 50);
+}
@@ -0,0 +1,98 @@
+use nbs::{
+    error::NbsError,
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    stretch::{Collision, Stretch, StretchReport},
+    Nbs, NbsFormat, Tick,
+};
+
+/// Builds a song at 10 ticks per second with a piano note at ticks 0-3, the key of every note is its tick.
+fn song() -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut layer = Layer::from_format(format);
+    for tick in 0..4 {
+        let mut note = Note::new(
+            instrument::PIANO,
+            Key::from_raw(tick as u8),
+            None,
+            None,
+            None,
+        );
+        note.convert_to(format, &mut Default::default());
+        layer.notes.insert(tick, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.header.song_tempo = 1000;
+    nbs.fix();
+    nbs
+}
+
+/// Returns the notes as `(tick, key)`, where the key is the tick before stretching.
+fn notes(nbs: &Nbs) -> Vec<(Tick, u8)> {
+    nbs.noteblocks.layers[0]
+        .notes
+        .iter()
+        .map(|(&tick, note)| (tick, note.key.get()))
+        .collect()
+}
+
+/// Halves the song, ticks 1 and 2 both land on tick 1 and ticks 1 and 3 are rounded.
+fn halve(collision: Collision) -> Stretch {
+    Stretch {
+        collision,
+        ..Stretch::factor(0.5)
+    }
+}
+
+#[test]
+fn keep_first() {
+    let mut nbs = song();
+    let report = nbs.stretch(&halve(Collision::KeepFirst)).unwrap();
+    assert_eq!(notes(&nbs), [(0, 0), (1, 1), (2, 3)]);
+    let expected = StretchReport {
+        rounded: 2,
+        dropped: 1,
+        tempo_changes: 0,
+    };
+    assert_eq!(report, expected);
+    assert_eq!(nbs.header.song_tempo, 500);
+}
+
+#[test]
+fn keep_last() {
+    let mut nbs = song();
+    let report = nbs.stretch(&halve(Collision::KeepLast)).unwrap();
+    assert_eq!(notes(&nbs), [(0, 0), (1, 2), (2, 3)]);
+    assert_eq!((report.rounded, report.dropped), (2, 1));
+}
+
+#[test]
+fn collision_error_changes_nothing() {
+    let mut nbs = song();
+    match nbs.stretch(&halve(Collision::Error)) {
+        Err(NbsError::NoteCollision { tick, layer }) => assert_eq!((tick, layer), (1, 0)),
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(notes(&nbs), [(0, 0), (1, 1), (2, 2), (3, 3)]);
+    assert_eq!(nbs.header.song_tempo, 1000);
+}
+
+#[test]
+fn loop_start_tick_is_stretched() {
+    let mut nbs = song();
+    nbs.header.is_loop = Some(true);
+    nbs.header.loop_start_tick = Some(3);
+    nbs.stretch(&Stretch::factor(2.0)).unwrap();
+    assert_eq!(nbs.header.loop_start_tick, Some(6));
+    assert_eq!(notes(&nbs), [(0, 0), (2, 1), (4, 2), (6, 3)]);
+    nbs.stretch(&halve(Collision::Error)).unwrap();
+    assert_eq!(nbs.header.loop_start_tick, Some(3));
+}
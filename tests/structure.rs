@@ -0,0 +1,273 @@
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    structure::{self, SchematicVersion},
+    Nbs, NbsFormat, Tick,
+};
+use std::convert::TryInto;
+
+/// Builds a song at 10 ticks per second, so every tick is a single redstone tick.
+fn song(notes: &[(Tick, Instrument)]) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut noteblocks = NoteBlocks::new();
+    for &(tick, instrument) in notes {
+        let mut note = Note::new(instrument, Key::default(), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        let mut layer = Layer::from_format(format);
+        layer.notes.insert(tick, note);
+        noteblocks.layers.push(layer);
+    }
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.header.song_tempo = 1000;
+    nbs.fix();
+    nbs
+}
+
+/// The tags of the NBT format that the exporters write.
+#[derive(Debug, PartialEq)]
+enum Nbt {
+    Short(i16),
+    Int(i32),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Nbt>),
+    Compound(Vec<(String, Nbt)>),
+    IntArray(Vec<i32>),
+}
+
+impl Nbt {
+    fn get(&self, name: &str) -> &Nbt {
+        match self {
+            Nbt::Compound(entries) => entries
+                .iter()
+                .find(|(entry, _)| entry == name)
+                .map(|(_, tag)| tag)
+                .unwrap_or_else(|| panic!("missing tag {}", name)),
+            tag => panic!("{:?} is not a compound", tag),
+        }
+    }
+
+    fn int(&self) -> i32 {
+        match *self {
+            Nbt::Short(value) => value as i32,
+            Nbt::Int(value) => value,
+            ref tag => panic!("{:?} is not a number", tag),
+        }
+    }
+}
+
+/// Reads the data of a gzip stream made of stored deflate blocks, as the exporters write it.
+fn gunzip(gzip: &[u8]) -> Vec<u8> {
+    assert_eq!(gzip[..3], [0x1F, 0x8B, 8]);
+    let mut data = Vec::new();
+    let mut rest = &gzip[10..];
+    loop {
+        let last = rest[0] & 1 == 1;
+        assert_eq!(rest[0] >> 1, 0, "only stored blocks are expected");
+        let length = u16::from_le_bytes([rest[1], rest[2]]) as usize;
+        data.extend_from_slice(&rest[5..5 + length]);
+        rest = &rest[5 + length..];
+        if last {
+            break;
+        }
+    }
+    assert_eq!(
+        u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize,
+        data.len()
+    );
+    data
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> &'a [u8] {
+    let (taken, rest) = data.split_at(length);
+    *data = rest;
+    taken
+}
+
+fn read_string(data: &mut &[u8]) -> String {
+    let length = u16::from_be_bytes(take(data, 2).try_into().unwrap()) as usize;
+    String::from_utf8(take(data, length).to_vec()).unwrap()
+}
+
+fn read_i32(data: &mut &[u8]) -> i32 {
+    i32::from_be_bytes(take(data, 4).try_into().unwrap())
+}
+
+fn read_payload(id: u8, data: &mut &[u8]) -> Nbt {
+    match id {
+        2 => Nbt::Short(i16::from_be_bytes(take(data, 2).try_into().unwrap())),
+        3 => Nbt::Int(read_i32(data)),
+        7 => {
+            let length = read_i32(data) as usize;
+            Nbt::ByteArray(take(data, length).to_vec())
+        }
+        8 => Nbt::String(read_string(data)),
+        9 => {
+            let id = take(data, 1)[0];
+            let length = read_i32(data);
+            Nbt::List((0..length).map(|_| read_payload(id, data)).collect())
+        }
+        10 => {
+            let mut entries = Vec::new();
+            loop {
+                let id = take(data, 1)[0];
+                if id == 0 {
+                    break Nbt::Compound(entries);
+                }
+                let name = read_string(data);
+                entries.push((name, read_payload(id, data)));
+            }
+        }
+        11 => {
+            let length = read_i32(data);
+            Nbt::IntArray((0..length).map(|_| read_i32(data)).collect())
+        }
+        id => panic!("unexpected tag {}", id),
+    }
+}
+
+/// Decompresses and reads a NBT file, returning the name and the tag of its root.
+fn read_nbt(gzip: &[u8]) -> (String, Nbt) {
+    let data = gunzip(gzip);
+    let mut data = &data[..];
+    let id = take(&mut data, 1)[0];
+    let name = read_string(&mut data);
+    let root = read_payload(id, &mut data);
+    assert!(data.is_empty());
+    (name, root)
+}
+
+/// Reads the varint palette indices of a schematic.
+fn block_indices(data: &[u8]) -> Vec<usize> {
+    let mut indices = Vec::new();
+    let mut index = 0;
+    let mut shift = 0;
+    for &byte in data {
+        index |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            indices.push(index);
+            index = 0;
+            shift = 0;
+        }
+    }
+    indices
+}
+
+/// Returns the palette of a schematic ordered by index.
+fn schematic_palette(palette: &Nbt) -> Vec<String> {
+    let mut entries: Vec<_> = match palette {
+        Nbt::Compound(entries) => entries
+            .iter()
+            .map(|(state, index)| (index.int(), state.clone()))
+            .collect(),
+        tag => panic!("{:?} is not a palette", tag),
+    };
+    entries.sort();
+    entries.into_iter().map(|(_, state)| state).collect()
+}
+
+/// Two notes at tick 0 and one at tick 2, which is reached through a repeater with a delay of 2.
+/// The line is at z = 1 between the two note blocks of tick 0, which makes the build 4 by 4 by 3 blocks.
+fn small_song() -> Nbs {
+    song(&[
+        (0, instrument::PIANO),
+        (0, instrument::BASS_DRUM),
+        (2, instrument::BELL),
+    ])
+}
+
+const NOTE_BLOCKS: [&str; 3] = [
+    "minecraft:note_block[instrument=harp,note=12,powered=false]",
+    "minecraft:note_block[instrument=basedrum,note=12,powered=false]",
+    "minecraft:note_block[instrument=bell,note=12,powered=false]",
+];
+
+fn check_schematic(schematic: &Nbt, palette: &Nbt, data: &Nbt) {
+    let size: Vec<_> = ["Width", "Height", "Length"]
+        .iter()
+        .map(|name| schematic.get(name).int())
+        .collect();
+    assert_eq!(size, [4, 4, 3]);
+    let palette = schematic_palette(palette);
+    assert_eq!(palette[0], "minecraft:air");
+    for state in NOTE_BLOCKS
+        .iter()
+        .chain(&["minecraft:stone", "minecraft:gold_block"])
+    {
+        assert!(palette.contains(&state.to_string()), "{} is missing", state);
+    }
+    let indices = match data {
+        Nbt::ByteArray(data) => block_indices(data),
+        tag => panic!("{:?} is not block data", tag),
+    };
+    assert_eq!(indices.len(), 4 * 4 * 3);
+    let note_blocks = indices
+        .iter()
+        .filter(|&&index| palette[index].starts_with("minecraft:note_block"))
+        .count();
+    assert_eq!(note_blocks, 3);
+}
+
+#[test]
+fn schematic_v2() {
+    let mut schem = Vec::new();
+    structure::encode_schematic(&small_song(), SchematicVersion::V2, &mut schem).unwrap();
+    let (name, schematic) = read_nbt(&schem);
+    assert_eq!(name, "Schematic");
+    assert_eq!(schematic.get("Version"), &Nbt::Int(2));
+    let palette = schematic.get("Palette");
+    assert_eq!(
+        schematic.get("PaletteMax").int() as usize,
+        schematic_palette(palette).len()
+    );
+    check_schematic(&schematic, palette, schematic.get("BlockData"));
+}
+
+#[test]
+fn schematic_v3() {
+    let mut schem = Vec::new();
+    structure::encode_schematic(&small_song(), SchematicVersion::V3, &mut schem).unwrap();
+    let (name, root) = read_nbt(&schem);
+    assert_eq!(name, "");
+    let schematic = root.get("Schematic");
+    assert_eq!(schematic.get("Version"), &Nbt::Int(3));
+    let blocks = schematic.get("Blocks");
+    check_schematic(schematic, blocks.get("Palette"), blocks.get("Data"));
+}
+
+#[test]
+fn vanilla_structure() {
+    let mut nbt = Vec::new();
+    structure::encode_structure(&small_song(), &mut nbt).unwrap();
+    let (_, root) = read_nbt(&nbt);
+    assert_eq!(
+        root.get("size"),
+        &Nbt::List(vec![Nbt::Int(4), Nbt::Int(4), Nbt::Int(3)])
+    );
+    let palette: Vec<_> = match root.get("palette") {
+        Nbt::List(states) => states
+            .iter()
+            .map(|state| match state.get("Name") {
+                Nbt::String(name) => name.clone(),
+                tag => panic!("{:?} is not a name", tag),
+            })
+            .collect(),
+        tag => panic!("{:?} is not a palette", tag),
+    };
+    let note_blocks = match root.get("blocks") {
+        Nbt::List(blocks) => blocks
+            .iter()
+            .filter(|block| palette[block.get("state").int() as usize] == "minecraft:note_block")
+            .count(),
+        tag => panic!("{:?} is not a list of blocks", tag),
+    };
+    assert_eq!(note_blocks, 3);
+    assert!(palette.contains(&"minecraft:gold_block".to_string()));
+}
@@ -0,0 +1,70 @@
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{CustomInstrumentInfo, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    stretch::Stretch,
+    Nbs, NbsFormat, Tick,
+};
+
+/// Builds a song at 10 ticks per second with a tempo changer note of each pitch, ten ticks apart.
+fn song(pitches: &[i16]) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut custom_instruments = CustomInstruments::new();
+    let tempo_changer = custom_instruments
+        .add(
+            16,
+            CustomInstrumentInfo {
+                instrument: Instrument::Custom(0),
+                name: String::from(CustomInstrumentInfo::TEMPO_CHANGER),
+                file_name: String::new(),
+                pitch: Key::default(),
+                press_key: false,
+            },
+        )
+        .unwrap();
+    let mut layer = Layer::from_format(format);
+    for (tick, &pitch) in pitches.iter().enumerate() {
+        let note = Note::new(tempo_changer, Key::default(), None, None, Some(pitch));
+        layer.notes.insert(tick as Tick * 10, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, custom_instruments);
+    nbs.header.song_tempo = 1000;
+    nbs.fix();
+    nbs
+}
+
+#[test]
+fn negative_pitches_change_the_tempo() {
+    let tempo_map = song(&[-300, 0, 150]).tempo_map();
+    let changes: Vec<(Tick, f64)> = tempo_map.changes().collect();
+    assert_eq!(changes, [(0, 20.0), (20, 10.0)]);
+}
+
+#[test]
+fn stretching_keeps_the_sign_of_tempo_changers() {
+    let mut nbs = song(&[-300, 0, 1]);
+    let report = nbs.stretch(&Stretch::factor(0.4)).unwrap();
+    assert_eq!(report.tempo_changes, 2);
+    let pitches: Vec<_> = nbs.noteblocks.layers[0]
+        .notes
+        .values()
+        .map(|note| note.pitch)
+        .collect();
+    // The pitch of 1 would be rounded to 0, which turns the tempo change off.
+    assert_eq!(pitches, [Some(-120), Some(0), Some(1)]);
+}
+
+#[test]
+fn stretched_tempo_stays_positive() {
+    let mut nbs = song(&[]);
+    nbs.header.song_tempo = 2;
+    nbs.stretch(&Stretch::factor(0.1)).unwrap();
+    assert_eq!(nbs.header.song_tempo, 1);
+}
@@ -0,0 +1,138 @@
+use nbs::{
+    error::NbsError,
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstrumentInfo, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::Key,
+        NoteBlocks,
+    },
+    transpose::{OutOfRange, Transpose, TransposeReport},
+    Nbs, NbsFormat, Tick,
+};
+
+/// Builds a song of version 5 with a piano note of the given key at each tick.
+fn song(notes: &[(Tick, u8)]) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut layer = Layer::from_format(format);
+    for &(tick, key) in notes {
+        let mut note = Note::new(instrument::PIANO, Key::from_raw(key), None, None, None);
+        note.convert_to(format, &mut Default::default());
+        layer.notes.insert(tick, note);
+    }
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.fix();
+    nbs
+}
+
+/// Returns the notes of the first layer as `(tick, key)`.
+fn keys(nbs: &Nbs) -> Vec<(Tick, u8)> {
+    nbs.noteblocks.layers[0]
+        .notes
+        .iter()
+        .map(|(&tick, note)| (tick, note.key.get()))
+        .collect()
+}
+
+fn octave_up(out_of_range: OutOfRange) -> Transpose {
+    Transpose {
+        out_of_range,
+        ..Transpose::semitones(12)
+    }
+}
+
+#[test]
+fn clamp() {
+    let mut nbs = song(&[(0, 10), (1, 80)]);
+    let report = nbs.transpose(&octave_up(OutOfRange::Clamp)).unwrap();
+    assert_eq!(keys(&nbs), [(0, 22), (1, 87)]);
+    let expected = TransposeReport {
+        transposed: 2,
+        clamped: 1,
+        ..Default::default()
+    };
+    assert_eq!(report, expected);
+}
+
+#[test]
+fn fold() {
+    let mut nbs = song(&[(0, 10), (1, 80), (2, 87)]);
+    let report = nbs.transpose(&octave_up(OutOfRange::Fold)).unwrap();
+    assert_eq!(keys(&nbs), [(0, 22), (1, 80), (2, 87)]);
+    assert_eq!(report.transposed, 3);
+    assert_eq!(report.folded, 2);
+    // Downwards as well, by as many octaves as needed.
+    let mut nbs = song(&[(0, 3)]);
+    let transpose = Transpose {
+        out_of_range: OutOfRange::Fold,
+        ..Transpose::semitones(-30)
+    };
+    nbs.transpose(&transpose).unwrap();
+    assert_eq!(keys(&nbs), [(0, 9)]);
+}
+
+#[test]
+fn drop() {
+    let mut nbs = song(&[(0, 10), (1, 80)]);
+    let report = nbs.transpose(&octave_up(OutOfRange::Drop)).unwrap();
+    assert_eq!(keys(&nbs), [(0, 22)]);
+    let expected = TransposeReport {
+        transposed: 1,
+        dropped: 1,
+        ..Default::default()
+    };
+    assert_eq!(report, expected);
+}
+
+#[test]
+fn error_changes_nothing() {
+    let mut nbs = song(&[(0, 10), (1, 80)]);
+    match nbs.transpose(&octave_up(OutOfRange::Error)) {
+        Err(NbsError::KeyOutOfRange { tick, key }) => assert_eq!((tick, key), (1, 92)),
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(keys(&nbs), [(0, 10), (1, 80)]);
+}
+
+#[test]
+fn cents_carry_into_the_key() {
+    let mut nbs = song(&[(0, 45)]);
+    nbs.noteblocks.layers[0].notes.get_mut(&0).unwrap().pitch = Some(60);
+    nbs.transpose(&Transpose::cents(50)).unwrap();
+    let note = &nbs.noteblocks.layers[0].notes[&0];
+    assert_eq!((note.key.get(), note.pitch), (46, Some(10)));
+}
+
+#[test]
+fn tempo_changers_are_left_untouched() {
+    let mut nbs = song(&[(0, 45)]);
+    let tempo_changer = nbs
+        .custom_instruments
+        .add(
+            16,
+            CustomInstrumentInfo {
+                instrument: Instrument::Custom(0),
+                name: String::from(CustomInstrumentInfo::TEMPO_CHANGER),
+                file_name: String::new(),
+                pitch: Key::default(),
+                press_key: false,
+            },
+        )
+        .unwrap();
+    let format = nbs.format();
+    let mut note = Note::new(tempo_changer, Key::from_raw(87), None, None, Some(300));
+    note.convert_to(format, &mut Default::default());
+    let mut layer = Layer::from_format(format);
+    layer.notes.insert(0, note);
+    nbs.insert_layer(1, layer);
+    // Would move the key of the tempo changer out of range, if it was transposed.
+    let report = nbs.transpose(&octave_up(OutOfRange::Error)).unwrap();
+    assert_eq!(report.transposed, 1);
+    assert_eq!(keys(&nbs), [(0, 57)]);
+    let note = &nbs.noteblocks.layers[1].notes[&0];
+    assert_eq!((note.key.get(), note.pitch), (87, Some(300)));
+    assert_eq!(nbs.tempo_map().ticks_per_second(0), 20.0);
+}
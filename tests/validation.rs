@@ -0,0 +1,154 @@
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstrumentInfo, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::{Key, Panning, Velocity},
+        NoteBlocks,
+    },
+    validation::{IssueKind, Location},
+    Nbs, NbsFormat, Tick,
+};
+
+/// Builds a song of the given version with a layer for every list of notes, `(tick, instrument)`.
+/// Neither the layers nor the notes have their format dependent fields filled.
+fn song(version: i8, layers: &[&[(Tick, Instrument)]]) -> Nbs {
+    let format = NbsFormat::OpenNoteBlockStudio(version);
+    let mut noteblocks = NoteBlocks::new();
+    for notes in layers {
+        let mut layer = Layer::from_format(NbsFormat::OpenNoteBlockStudio(1));
+        for &(tick, instrument) in notes.iter() {
+            let note = Note::new(instrument, Key::default(), None, None, None);
+            layer.notes.insert(tick, note);
+        }
+        noteblocks.layers.push(layer);
+    }
+    Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new())
+}
+
+fn add_custom(nbs: &mut Nbs, name: &str) -> Instrument {
+    let info = CustomInstrumentInfo {
+        instrument: Instrument::Custom(0),
+        name: name.to_string(),
+        file_name: String::new(),
+        pitch: Key::default(),
+        press_key: false,
+    };
+    nbs.custom_instruments.add(16, info).unwrap()
+}
+
+#[test]
+fn custom_ids_are_renumbered_with_their_notes() {
+    let mut nbs = song(5, &[]);
+    let first = add_custom(&mut nbs, "first");
+    let second = add_custom(&mut nbs, "second");
+    nbs.noteblocks = song(5, &[&[(0, first), (1, instrument::PIANO)], &[(0, second)]]).noteblocks;
+    nbs.fix();
+    // The custom instruments now start after 17 vanilla instruments, so both ids are off by one.
+    nbs.header.vannila_instrument_count = Some(17);
+    let issues = nbs.validate();
+    let order = issues
+        .iter()
+        .filter(|issue| matches!(issue.kind, IssueKind::CustomInstrumentOrder { .. }))
+        .count();
+    assert_eq!(order, 2);
+
+    assert!(nbs.fix().is_empty());
+    let ids: Vec<_> = nbs
+        .custom_instruments
+        .iter()
+        .map(|info| info.instrument)
+        .collect();
+    assert_eq!(ids, [Instrument::Custom(17), Instrument::Custom(18)]);
+    let name = |tick: Tick, layer: usize| {
+        let instrument = nbs.noteblocks.layers[layer].notes[&tick].instrument;
+        nbs.custom_instruments
+            .get(instrument)
+            .map(|info| info.name.as_str())
+    };
+    // The first note moved to the old id of the second instrument, but still belongs to the first one.
+    assert_eq!(name(0, 0), Some("first"));
+    assert_eq!(name(0, 1), Some("second"));
+    assert_eq!(
+        nbs.noteblocks.layers[0].notes[&1].instrument,
+        instrument::PIANO
+    );
+}
+
+#[test]
+fn missing_fields_are_filled() {
+    for &version in &[4, 5] {
+        let mut nbs = song(version, &[&[(0, instrument::PIANO)]]);
+        nbs.header.is_loop = None;
+        nbs.header.max_loop_count = None;
+        nbs.header.loop_start_tick = None;
+        let missing = |nbs: &Nbs, location: Location| {
+            let mut fields: Vec<_> = nbs
+                .validate()
+                .into_iter()
+                .filter(|issue| issue.location == location)
+                .filter_map(|issue| match issue.kind {
+                    IssueKind::MissingField(field) => Some(field),
+                    _ => None,
+                })
+                .collect();
+            fields.sort_unstable();
+            fields
+        };
+        let note = Location::Note { tick: 0, layer: 0 };
+        assert_eq!(
+            missing(&nbs, Location::Header),
+            ["is_loop", "loop_start_tick", "max_loop_count"]
+        );
+        let layer_fields: &[&str] = match version {
+            4 => &["locked", "stereo"],
+            _ => &["locked", "solo", "stereo"],
+        };
+        assert_eq!(missing(&nbs, Location::Layer(0)), layer_fields);
+        assert_eq!(missing(&nbs, note), ["panning", "pitch", "velocity"]);
+
+        assert!(nbs.fix().is_empty(), "version {}", version);
+        assert_eq!(nbs.header.is_loop, Some(false));
+        assert_eq!(nbs.header.max_loop_count, Some(0));
+        assert_eq!(nbs.header.loop_start_tick, Some(0));
+        let layer = &nbs.noteblocks.layers[0];
+        assert_eq!(layer.locked, Some(false));
+        assert_eq!(layer.solo, if version >= 5 { Some(false) } else { None });
+        assert_eq!(layer.stereo, Some(Panning::CENTER));
+        let note = &layer.notes[&0];
+        assert_eq!(note.velocity, Some(Velocity::default()));
+        assert_eq!(note.panning, Some(Panning::CENTER));
+        assert_eq!(note.pitch, Some(0));
+    }
+}
+
+#[test]
+fn unfixable_issues_remain() {
+    let late = i16::MAX as Tick + 10;
+    let mut nbs = song(
+        5,
+        &[
+            &[(-1, instrument::PIANO), (0, instrument::PIANO)],
+            &[(late, instrument::PIANO)],
+        ],
+    );
+    let remaining = nbs.fix();
+    assert!(remaining.iter().all(|issue| !issue.kind.is_fixable()));
+    let kinds: Vec<_> = remaining
+        .iter()
+        .filter_map(|issue| match issue.location {
+            Location::Note { tick, layer } => Some((tick, layer, issue.kind)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (-1, 0, IssueKind::NegativeTick),
+            (late, 1, IssueKind::TickOutOfRange)
+        ]
+    );
+    // The notes are kept, so they can be moved by hand.
+    assert_eq!(nbs.noteblocks.events().count(), 3);
+}
@@ -0,0 +1,106 @@
+use nbs::{
+    header::Header,
+    noteblocks::{
+        instrument::{self, CustomInstrumentInfo, CustomInstruments, Instrument},
+        layer::Layer,
+        note::Note,
+        value::{Key, Panning, Velocity, Volume},
+        NoteBlocks,
+    },
+    validation::IssueKind,
+    Nbs, NbsFormat,
+};
+
+#[test]
+fn constructors() {
+    assert_eq!(Key::new(87).map(Key::get), Some(87));
+    assert_eq!(Key::new(88), None);
+    assert_eq!(Velocity::new(101), None);
+    assert_eq!(Panning::new(200), Some(Panning::MAX));
+    assert_eq!(Panning::new(201), None);
+    assert_eq!(Volume::new(0), Some(Volume::MIN));
+
+    assert_eq!(Key::saturating(-5), Key::MIN);
+    assert_eq!(Key::saturating(1000), Key::MAX);
+    assert_eq!(Velocity::saturating(150).get(), 100);
+    assert_eq!(Panning::saturating(150).get(), 150);
+    assert_eq!(Volume::saturating(i32::MIN), Volume::MIN);
+
+    // Raw values keep any byte, but are not valid.
+    for value in 0..=u8::MAX {
+        assert_eq!(Panning::from_raw(value).get(), value);
+        assert_eq!(Panning::from_raw(value).is_valid(), value <= 200);
+        assert_eq!(Key::from_raw(value).is_valid(), value <= 87);
+    }
+}
+
+#[test]
+fn defaults() {
+    assert_eq!(Key::default().get(), 45);
+    assert_eq!(Velocity::default(), Velocity::MAX);
+    assert_eq!(Panning::default(), Panning::CENTER);
+    assert_eq!(Volume::default(), Volume::MAX);
+}
+
+fn encode(nbs: &Nbs) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    nbs.encode(&mut buffer).unwrap();
+    buffer
+}
+
+#[test]
+fn values_above_127_round_trip() {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut layer = Layer::from_format(format);
+    layer.stereo = Panning::new(180);
+    let mut note = Note::new(
+        instrument::PIANO,
+        Key::default(),
+        None,
+        Panning::new(200),
+        None,
+    );
+    note.convert_to(format, &mut Default::default());
+    layer.notes.insert(0, note);
+    let mut noteblocks = NoteBlocks::new();
+    noteblocks.layers.push(layer);
+    let mut nbs = Nbs::from_componets(Header::new(format), noteblocks, CustomInstruments::new());
+    nbs.fix();
+    // Set after `fix`, which would saturate them.
+    nbs.noteblocks.layers[0].volume = Volume::from_raw(255);
+    let issues: Vec<_> = nbs.validate().into_iter().map(|issue| issue.kind).collect();
+    assert_eq!(issues, [IssueKind::VolumeOutOfRange(Volume::from_raw(255))]);
+
+    let buffer = encode(&nbs);
+    let decoded = Nbs::decode(&mut &buffer[..]).unwrap();
+    let layer = &decoded.noteblocks.layers[0];
+    assert_eq!(layer.stereo, Panning::new(180));
+    assert_eq!(layer.volume, Volume::from_raw(255));
+    assert_eq!(layer.notes[&0].panning, Panning::new(200));
+    assert_eq!(encode(&decoded), buffer);
+}
+
+#[test]
+fn more_than_127_custom_instruments_round_trip() {
+    let format = NbsFormat::OpenNoteBlockStudio(5);
+    let mut custom_instruments = CustomInstruments::new();
+    for index in 0..200 {
+        let info = CustomInstrumentInfo {
+            instrument: Instrument::Custom(0),
+            name: index.to_string(),
+            file_name: String::new(),
+            pitch: Key::default(),
+            press_key: false,
+        };
+        custom_instruments.add(16, info).unwrap();
+    }
+    let mut nbs = Nbs::from_componets(Header::new(format), NoteBlocks::new(), custom_instruments);
+    nbs.fix();
+    let decoded = Nbs::decode(&mut &encode(&nbs)[..]).unwrap();
+    let last = decoded.custom_instruments.iter().last().unwrap();
+    assert_eq!(decoded.custom_instruments.iter().count(), 200);
+    assert_eq!(
+        (last.instrument, last.name.as_str()),
+        (Instrument::Custom(215), "199")
+    );
+}